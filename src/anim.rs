@@ -48,7 +48,7 @@ pub struct Frame<'a> {
 const ANIM_MAGIC: u32 = 0x4d494e41;
 
 impl Anim {
-    pub fn fromFile<R: Read + Seek + Copy>(mut fp: R) -> Result<Anim, Box<dyn Error>> {
+    pub fn fromFile<R: Read + Seek>(mut fp: R) -> Result<Anim, Box<dyn Error + Send + Sync>> {
         let anim = fp.read_u32::<LE>()?;
         assert_eq!(anim, ANIM_MAGIC);
         let _scale = fp.read_u8()?;
@@ -107,7 +107,7 @@ impl Anim {
         Ok(Anim { layers, frames, width, height })
     }
 
-    pub fn getFrame(&self, idx: usize) -> Result<Option<Frame>, Box<dyn Error>> {
+    pub fn getFrame(&self, idx: usize) -> Result<Option<Frame<'_>>, Box<dyn Error + Send + Sync>> {
         let frame_info = match self.frames.get(idx) {
             Some(info) => info,
             None => return Ok(None),
@@ -12,7 +12,7 @@ fn scrub_path(path: &Path) -> Result<(), Box<dyn Error>> {
         }
     } else {
         println!("Scrubbing {}", path.to_string_lossy());
-        let dimension = image_dimensions(&path)?;
+        let dimension = image_dimensions(path)?;
         let empty_image = RgbImage::new(dimension.0, dimension.1);
         empty_image.save(&path)?;
     }
@@ -0,0 +1,231 @@
+use std::io::{Read, Cursor};
+use std::error::Error;
+use byteorder::{ReadBytesExt, LE};
+
+// The dat files store every field as an array over all entries, one field after another.
+// Some fields only cover a range of the entries.
+const UNIT_COUNT: usize = 228;
+const UNIT_SOUND_COUNT: usize = 106;
+const BUILDING_COUNT: usize = 96;
+const WEAPON_COUNT: usize = 130;
+const FLINGY_COUNT: usize = 209;
+
+/// Weapon id of units without a weapon.
+pub const NO_WEAPON: u8 = 130;
+
+/// Pixels from the unit center to each edge, all inclusive.
+#[derive(Copy, Clone, Debug)]
+pub struct UnitDimensions {
+    pub left: u16,
+    pub up: u16,
+    pub right: u16,
+    pub down: u16,
+}
+
+pub struct Unit {
+    pub flingy: u8,
+    pub shield_enable: bool,
+    pub shield_amount: u16,
+    /// in 1/256 hit points
+    pub hit_points: u32,
+    pub ground_weapon: u8,
+    pub max_ground_hits: u8,
+    /// in 32 pixel tiles
+    pub sight_range: u8,
+    pub armor: u8,
+    pub dimensions: UnitDimensions,
+}
+
+/// How a weapon gets its damage to the target.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum WeaponBehavior {
+    /// a projectile flies to where the target was
+    flyToSite,
+    /// a projectile follows the target
+    flyToTarget,
+    /// the hit appears on the target straight away, as for melee attacks
+    appearOnTarget,
+    persistOnSite,
+    appearOnSite,
+    appearOnAttacker,
+    selfDestruct,
+    /// a projectile bounces between targets
+    bounce,
+    attackNearbyArea,
+    /// a projectile flies out to the maximum range
+    goToMaxRange,
+    unknown(u8),
+}
+
+impl WeaponBehavior {
+    fn fromByte(value: u8) -> WeaponBehavior {
+        match value {
+            0 => WeaponBehavior::flyToSite,
+            1 => WeaponBehavior::flyToTarget,
+            2 => WeaponBehavior::appearOnTarget,
+            3 => WeaponBehavior::persistOnSite,
+            4 => WeaponBehavior::appearOnSite,
+            5 => WeaponBehavior::appearOnAttacker,
+            6 => WeaponBehavior::selfDestruct,
+            7 => WeaponBehavior::bounce,
+            8 => WeaponBehavior::attackNearbyArea,
+            9 => WeaponBehavior::goToMaxRange,
+            value => WeaponBehavior::unknown(value),
+        }
+    }
+
+    /// Whether the weapon fires something that travels to the target.
+    pub fn firesProjectile(&self) -> bool {
+        matches!(
+            self,
+            WeaponBehavior::flyToSite | WeaponBehavior::flyToTarget | WeaponBehavior::bounce | WeaponBehavior::goToMaxRange
+        )
+    }
+}
+
+/// Damage type of a weapon, deciding how much armor and unit size reduce it.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum DamageType {
+    independent,
+    explosive,
+    concussive,
+    normal,
+    ignoreArmor,
+    unknown(u8),
+}
+
+impl DamageType {
+    fn fromByte(value: u8) -> DamageType {
+        match value {
+            0 => DamageType::independent,
+            1 => DamageType::explosive,
+            2 => DamageType::concussive,
+            3 => DamageType::normal,
+            4 => DamageType::ignoreArmor,
+            value => DamageType::unknown(value),
+        }
+    }
+}
+
+pub struct Weapon {
+    /// flingy.dat entry of the projectile or hit graphic
+    pub flingy: u32,
+    /// in pixels
+    pub min_range: u32,
+    /// in pixels
+    pub max_range: u32,
+    pub damage_type: DamageType,
+    pub behavior: WeaponBehavior,
+    pub damage_amount: u16,
+    /// in game frames
+    pub cooldown: u8,
+    /// hits per attack
+    pub damage_factor: u8,
+}
+
+pub struct Flingy {
+    /// in 1/256 pixels per game frame
+    pub top_speed: u32,
+}
+
+fn skip(fp: &mut Cursor<&[u8]>, size: usize) {
+    fp.set_position(fp.position() + size as u64);
+}
+
+fn readArray<T>(
+    fp: &mut Cursor<&[u8]>,
+    count: usize,
+    read: fn(&mut Cursor<&[u8]>) -> std::io::Result<T>,
+) -> std::io::Result<Vec<T>> {
+    (0..count).map(|_| read(fp)).collect()
+}
+
+pub fn readUnits<R: Read>(mut fp: R) -> Result<Vec<Unit>, Box<dyn Error + Send + Sync>> {
+    let mut data = Vec::new();
+    fp.read_to_end(&mut data)?;
+    let fp = &mut Cursor::new(&data[..]);
+    let flingy = readArray(fp, UNIT_COUNT, |fp| fp.read_u8())?;
+    // subunits, infestation, construction animation, unit direction
+    skip(fp, 2 * 2 * UNIT_COUNT + 2 * BUILDING_COUNT + 4 * UNIT_COUNT + UNIT_COUNT);
+    let shield_enable = readArray(fp, UNIT_COUNT, |fp| fp.read_u8())?;
+    let shield_amount = readArray(fp, UNIT_COUNT, |fp| fp.read_u16::<LE>())?;
+    let hit_points = readArray(fp, UNIT_COUNT, |fp| fp.read_u32::<LE>())?;
+    // elevation, flags, rank, AI orders, attack orders
+    skip(fp, 8 * UNIT_COUNT);
+    let ground_weapon = readArray(fp, UNIT_COUNT, |fp| fp.read_u8())?;
+    let max_ground_hits = readArray(fp, UNIT_COUNT, |fp| fp.read_u8())?;
+    // air weapon, max air hits, AI internal, special ability flags, target acquisition range
+    skip(fp, 3 * UNIT_COUNT + 4 * UNIT_COUNT + UNIT_COUNT);
+    let sight_range = readArray(fp, UNIT_COUNT, |fp| fp.read_u8())?;
+    // armor upgrade, unit size
+    skip(fp, 2 * UNIT_COUNT);
+    let armor = readArray(fp, UNIT_COUNT, |fp| fp.read_u8())?;
+    // right click action, sounds, staredit placement box, addon position
+    skip(fp, UNIT_COUNT + 2 * UNIT_SOUND_COUNT + 2 * 2 * UNIT_COUNT + 4 * 2 * UNIT_SOUND_COUNT);
+    skip(fp, 4 * UNIT_COUNT + 4 * BUILDING_COUNT);
+    let dimensions = readArray(fp, UNIT_COUNT, |fp| Ok(UnitDimensions {
+        left: fp.read_u16::<LE>()?,
+        up: fp.read_u16::<LE>()?,
+        right: fp.read_u16::<LE>()?,
+        down: fp.read_u16::<LE>()?,
+    }))?;
+    Ok((0..UNIT_COUNT)
+        .map(|i| Unit {
+            flingy: flingy[i],
+            shield_enable: shield_enable[i] != 0,
+            shield_amount: shield_amount[i],
+            hit_points: hit_points[i],
+            ground_weapon: ground_weapon[i],
+            max_ground_hits: max_ground_hits[i],
+            sight_range: sight_range[i],
+            armor: armor[i],
+            dimensions: dimensions[i],
+        })
+        .collect())
+}
+
+pub fn readWeapons<R: Read>(mut fp: R) -> Result<Vec<Weapon>, Box<dyn Error + Send + Sync>> {
+    let mut data = Vec::new();
+    fp.read_to_end(&mut data)?;
+    let fp = &mut Cursor::new(&data[..]);
+    // label
+    skip(fp, 2 * WEAPON_COUNT);
+    let flingy = readArray(fp, WEAPON_COUNT, |fp| fp.read_u32::<LE>())?;
+    // unused, target flags
+    skip(fp, (1 + 2) * WEAPON_COUNT);
+    let min_range = readArray(fp, WEAPON_COUNT, |fp| fp.read_u32::<LE>())?;
+    let max_range = readArray(fp, WEAPON_COUNT, |fp| fp.read_u32::<LE>())?;
+    // damage upgrade
+    skip(fp, WEAPON_COUNT);
+    let damage_type = readArray(fp, WEAPON_COUNT, |fp| fp.read_u8())?;
+    let behavior = readArray(fp, WEAPON_COUNT, |fp| fp.read_u8())?;
+    // remove after, explosion type, splash radii
+    skip(fp, 2 * WEAPON_COUNT + 3 * 2 * WEAPON_COUNT);
+    let damage_amount = readArray(fp, WEAPON_COUNT, |fp| fp.read_u16::<LE>())?;
+    // damage bonus
+    skip(fp, 2 * WEAPON_COUNT);
+    let cooldown = readArray(fp, WEAPON_COUNT, |fp| fp.read_u8())?;
+    let damage_factor = readArray(fp, WEAPON_COUNT, |fp| fp.read_u8())?;
+    Ok((0..WEAPON_COUNT)
+        .map(|i| Weapon {
+            flingy: flingy[i],
+            min_range: min_range[i],
+            max_range: max_range[i],
+            damage_type: DamageType::fromByte(damage_type[i]),
+            behavior: WeaponBehavior::fromByte(behavior[i]),
+            damage_amount: damage_amount[i],
+            cooldown: cooldown[i],
+            damage_factor: damage_factor[i],
+        })
+        .collect())
+}
+
+pub fn readFlingies<R: Read>(mut fp: R) -> Result<Vec<Flingy>, Box<dyn Error + Send + Sync>> {
+    let mut data = Vec::new();
+    fp.read_to_end(&mut data)?;
+    let fp = &mut Cursor::new(&data[..]);
+    // sprite
+    skip(fp, 2 * FLINGY_COUNT);
+    let top_speed = readArray(fp, FLINGY_COUNT, |fp| fp.read_u32::<LE>())?;
+    Ok(top_speed.into_iter().map(|top_speed| Flingy { top_speed }).collect())
+}
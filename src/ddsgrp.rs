@@ -0,0 +1,44 @@
+use std::io::{Read, Cursor};
+use std::error::Error;
+use byteorder::{ReadBytesExt, LE};
+use image::{DynamicImage, RgbaImage};
+use image::codecs::dds::DdsDecoder;
+
+/// A remastered `.dds.grp`: a list of standalone DDS images, used for graphics without
+/// frame offsets or layers such as wireframes and command card icons.
+pub struct DdsGrp {
+    frames: Vec<DynamicImage>,
+    pub width: u16,
+    pub height: u16,
+}
+
+impl DdsGrp {
+    pub fn fromFile<R: Read>(mut fp: R) -> Result<DdsGrp, Box<dyn Error + Send + Sync>> {
+        let frame_count = fp.read_u16::<LE>()?;
+        let _scale = fp.read_u8()?;
+        let _version = fp.read_u8()?;
+        let width = fp.read_u16::<LE>()?;
+        let height = fp.read_u16::<LE>()?;
+        let mut frames = Vec::with_capacity(frame_count as usize);
+        for _ in 0..frame_count {
+            let _unknown = fp.read_u32::<LE>()?;
+            let _width = fp.read_u16::<LE>()?;
+            let _height = fp.read_u16::<LE>()?;
+            let size = fp.read_u32::<LE>()?;
+            // read the whole image so a decoder stopping early doesn't shift the next frame
+            let mut data = vec![0u8; size as usize];
+            fp.read_exact(&mut data)?;
+            let dds = DdsDecoder::new(Cursor::new(data))?;
+            frames.push(DynamicImage::from_decoder(dds)?);
+        }
+        Ok(DdsGrp { frames, width, height })
+    }
+
+    pub fn frameCount(&self) -> usize {
+        self.frames.len()
+    }
+
+    pub fn getFrame(&self, idx: usize) -> Option<RgbaImage> {
+        self.frames.get(idx).map(|img| img.to_rgba8())
+    }
+}
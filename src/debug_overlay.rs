@@ -0,0 +1,134 @@
+use image::{RgbaImage, Rgba};
+
+/// Where a frame ended up on the sheets, for drawing the overlay over it.
+pub struct DebugCell {
+    pub img_idx: usize,
+    pub x: i32,
+    pub y: i32,
+    pub width: i32,
+    pub height: i32,
+    /// Position of the frame anchor relative to the cell, in half pixels.
+    pub anchor_x2: (i32, i32),
+    pub radial_offset_x2: (i32, i32),
+    pub source_idx: i32,
+}
+
+const BORDER: Rgba<u8> = Rgba([255, 0, 255, 255]);
+const CENTER: Rgba<u8> = Rgba([0, 255, 0, 255]);
+const RADIAL: Rgba<u8> = Rgba([255, 64, 0, 255]);
+const LABEL: Rgba<u8> = Rgba([255, 255, 255, 255]);
+const LABEL_BACKGROUND: Rgba<u8> = Rgba([0, 0, 0, 255]);
+
+// 3x5 digit glyphs, one bit per pixel, row by row starting at the top left
+const DIGITS: [u16; 10] = [
+    0b111_101_101_101_111,
+    0b010_110_010_010_111,
+    0b111_001_111_100_111,
+    0b111_001_111_001_111,
+    0b101_101_111_001_001,
+    0b111_100_111_001_111,
+    0b111_100_111_101_111,
+    0b111_001_001_001_001,
+    0b111_101_111_101_111,
+    0b111_101_111_001_111,
+];
+
+struct Canvas<'a> {
+    image: &'a mut RgbaImage,
+    cell: &'a DebugCell,
+}
+
+impl Canvas<'_> {
+    /// Draws at cell coordinates, clipped to the cell.
+    fn put(&mut self, x: i32, y: i32, color: Rgba<u8>) {
+        if x < 0 || y < 0 || x >= self.cell.width || y >= self.cell.height { return; }
+        self.image.put_pixel((self.cell.x + x) as u32, (self.cell.y + y) as u32, color);
+    }
+
+    fn line(&mut self, from: (i32, i32), to: (i32, i32), color: Rgba<u8>) {
+        let (dx, dy) = ((to.0 - from.0).abs(), -(to.1 - from.1).abs());
+        let (step_x, step_y) = ((to.0 - from.0).signum(), (to.1 - from.1).signum());
+        let (mut x, mut y) = from;
+        let mut error = dx + dy;
+        loop {
+            self.put(x, y, color);
+            if (x, y) == to { break; }
+            let doubled_error = 2 * error;
+            if doubled_error >= dy { error += dy; x += step_x; }
+            if doubled_error <= dx { error += dx; y += step_y; }
+        }
+    }
+
+    fn border(&mut self) {
+        let (right, bottom) = (self.cell.width - 1, self.cell.height - 1);
+        self.line((0, 0), (right, 0), BORDER);
+        self.line((right, 0), (right, bottom), BORDER);
+        self.line((right, bottom), (0, bottom), BORDER);
+        self.line((0, bottom), (0, 0), BORDER);
+    }
+
+    /// Dashed lines through the anchor, which may sit between two pixels.
+    fn crosshair(&mut self) {
+        let x_center1 = (self.cell.anchor_x2.0 - 1).div_euclid(2);
+        let x_center2 = self.cell.anchor_x2.0.div_euclid(2);
+        let y_center1 = (self.cell.anchor_x2.1 - 1).div_euclid(2);
+        let y_center2 = self.cell.anchor_x2.1.div_euclid(2);
+        for y in 0..self.cell.height {
+            let distance = if y <= y_center1 { y_center1 - y } else { y - y_center2 };
+            if distance % 8 >= 4 {
+                self.put(x_center1, y, CENTER);
+                self.put(x_center2, y, CENTER);
+            }
+        }
+        for x in 0..self.cell.width {
+            let distance = if x <= x_center1 { x_center1 - x } else { x - x_center2 };
+            if distance % 8 >= 4 {
+                self.put(x, y_center1, CENTER);
+                self.put(x, y_center2, CENTER);
+            }
+        }
+    }
+
+    /// The radial offset as a line ending at the anchor.
+    fn radialOffset(&mut self) {
+        let anchor = (self.cell.anchor_x2.0.div_euclid(2), self.cell.anchor_x2.1.div_euclid(2));
+        let origin = (
+            (self.cell.anchor_x2.0 - self.cell.radial_offset_x2.0).div_euclid(2),
+            (self.cell.anchor_x2.1 - self.cell.radial_offset_x2.1).div_euclid(2),
+        );
+        self.line(origin, anchor, RADIAL);
+    }
+
+    fn label(&mut self, number: i32) {
+        let text = number.to_string();
+        for x in 0..(text.len() as i32 * 4 + 1) {
+            for y in 0..7 {
+                self.put(1 + x, 1 + y, LABEL_BACKGROUND);
+            }
+        }
+        for (i, digit) in text.bytes().enumerate() {
+            let glyph = DIGITS[(digit - b'0') as usize];
+            for row in 0..5 {
+                for col in 0..3 {
+                    if glyph >> (14 - (row * 3 + col)) & 1 == 1 {
+                        self.put(2 + i as i32 * 4 + col, 2 + row, LABEL);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Copies of `sheets` with cell borders, frame anchors, radial offsets and source frame
+/// indices drawn over every cell.
+pub fn renderDebugSheets(sheets: &[RgbaImage], cells: &[DebugCell]) -> Vec<RgbaImage> {
+    let mut output = sheets.to_vec();
+    for cell in cells {
+        let mut canvas = Canvas { image: &mut output[cell.img_idx], cell };
+        canvas.border();
+        canvas.crosshair();
+        canvas.radialOffset();
+        canvas.label(cell.source_idx);
+    }
+    output
+}
@@ -0,0 +1,244 @@
+use std::f64::consts::PI;
+
+/// How the frames or directions of a sprite are arranged around the circle.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Orientation {
+    /// Heading of the first frame, in degrees clockwise from north.
+    pub start_angle: f64,
+    pub clockwise: bool,
+    /// For sources that only cover half the circle, the axis the other half is mirrored
+    /// over, in degrees clockwise from north. `None` when the frames go all the way round.
+    pub mirror_axis: Option<f64>,
+}
+
+/// StarCraft: 17 frames from north to south clockwise, the west side mirrored.
+pub const STARCRAFT_ORIENTATION: Orientation = Orientation {
+    start_angle: 0.0,
+    clockwise: true,
+    mirror_axis: Some(0.0),
+};
+
+/// Factorio: directions start at north and go clockwise all the way round.
+pub const FACTORIO_ORIENTATION: Orientation = Orientation {
+    start_angle: 0.0,
+    clockwise: true,
+    mirror_axis: None,
+};
+
+impl Orientation {
+    /// Heading of frame `index` out of `count`, in degrees clockwise from north.
+    pub fn heading(&self, index: i32, count: i32) -> f64 {
+        let step = match self.mirror_axis {
+            Some(_) if count > 1 => 180.0 / (count - 1) as f64,
+            _ => 360.0 / count as f64,
+        };
+        let sign = if self.clockwise { 1.0 } else { -1.0 };
+        (self.start_angle + sign * index as f64 * step).rem_euclid(360.0)
+    }
+}
+
+/// The source frame drawn for one output direction.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Facing {
+    /// Index among the source frames of one animation step.
+    pub source: i32,
+    /// Whether the frame is flipped horizontally.
+    pub mirrored: bool,
+    /// Clockwise rotation applied after mirroring, in radians.
+    pub rotation: f32,
+    /// Heading the frame ends up drawn at, in degrees clockwise from north.
+    pub heading: f64,
+}
+
+/// Number of facings around the full circle when `source_count` frames cover the half
+/// circle from north to south, both included, and the west side mirrors the east side.
+pub fn fullCircleCount(source_count: i32) -> i32 {
+    if source_count > 1 { 2 * (source_count - 1) } else { 1 }
+}
+
+/// Headings closer than this, in degrees, count as equally near.
+const TIE_TOLERANCE: f64 = 1e-9;
+
+fn angleBetween(from: f64, to: f64) -> f64 {
+    (to - from + 180.0).rem_euclid(360.0) - 180.0
+}
+
+/// Source frame `source` reflected over the mirror axis. Flipping the image reflects it
+/// over the north-south axis, any other axis needs a rotation on top.
+fn mirroredFacing(source: i32, source_count: i32, orientation: &Orientation, mirror_axis: f64) -> Facing {
+    Facing {
+        source,
+        mirrored: true,
+        rotation: (2.0 * mirror_axis).to_radians() as f32,
+        heading: (2.0 * mirror_axis - orientation.heading(source, source_count)).rem_euclid(360.0),
+    }
+}
+
+/// The source frame for facing `facing` of the full circle, where facings past the last
+/// source frame continue on the mirrored side.
+pub fn sourceFacing(facing: i32, source_count: i32, orientation: &Orientation) -> Facing {
+    let full_count = match orientation.mirror_axis {
+        Some(_) => fullCircleCount(source_count),
+        None => source_count,
+    };
+    let facing = facing.rem_euclid(full_count);
+    match orientation.mirror_axis {
+        Some(mirror_axis) if facing >= source_count => {
+            mirroredFacing(full_count - facing, source_count, orientation, mirror_axis)
+        }
+        _ => Facing {
+            source: facing,
+            mirrored: false,
+            rotation: 0.0,
+            heading: orientation.heading(facing, source_count),
+        },
+    }
+}
+
+/// Picks the nearest source facing, mirrored ones included, for each of the
+/// `target_count` directions of `target`. With `rotate`, the frame is turned the rest of
+/// the way to the direction, which matters once the output has more directions than
+/// the source.
+pub fn mapDirections(
+    target_count: i32,
+    target: &Orientation,
+    source_count: i32,
+    source: &Orientation,
+    rotate: bool,
+) -> Vec<Facing> {
+    let mut candidates: Vec<Facing> = (0..source_count)
+        .map(|index| sourceFacing(index, source_count, source))
+        .collect();
+    if let Some(mirror_axis) = source.mirror_axis {
+        candidates.extend((0..source_count).map(|index| mirroredFacing(index, source_count, source, mirror_axis)));
+    }
+    (0..target_count)
+        .map(|direction| {
+            let heading = target.heading(direction, target_count);
+            let error = |facing: &Facing| angleBetween(facing.heading, heading).abs();
+            // ties go to the source frame nearer the first one, the same on both sides of
+            // the mirror axis, and to the unmirrored frame on the axis itself
+            let mut facing = *candidates.iter()
+                .min_by(|a, b| {
+                    let (error_a, error_b) = (error(a), error(b));
+                    if (error_a - error_b).abs() > TIE_TOLERANCE {
+                        error_a.partial_cmp(&error_b).unwrap()
+                    } else {
+                        (a.source, a.mirrored).cmp(&(b.source, b.mirrored))
+                    }
+                })
+                .unwrap();
+            if rotate {
+                facing.rotation += (angleBetween(facing.heading, heading) * PI / 180.0) as f32;
+                facing.heading = heading;
+            }
+            facing
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SOURCE_COUNT: i32 = 17;
+    const TARGET_COUNTS: [i32; 11] = [1, 3, 4, 5, 7, 8, 9, 16, 31, 32, 64];
+
+    fn starcraftMapping(target_count: i32, rotate: bool) -> Vec<Facing> {
+        mapDirections(target_count, &FACTORIO_ORIENTATION, SOURCE_COUNT, &STARCRAFT_ORIENTATION, rotate)
+    }
+
+    #[test]
+    fn sourceFacingWrapsAroundTheMirroredSide() {
+        for facing in 0..2 * fullCircleCount(SOURCE_COUNT) {
+            let full = facing % fullCircleCount(SOURCE_COUNT);
+            let result = sourceFacing(facing, SOURCE_COUNT, &STARCRAFT_ORIENTATION);
+            assert_eq!(result.mirrored, full >= SOURCE_COUNT, "facing {}", facing);
+            assert_eq!(result.source, if full >= SOURCE_COUNT { 32 - full } else { full }, "facing {}", facing);
+            assert!((result.heading - full as f64 * 11.25).abs() < 1e-9, "facing {}", facing);
+        }
+    }
+
+    #[test]
+    fn picksTheNearestSourceFacing() {
+        let all: Vec<Facing> = (0..fullCircleCount(SOURCE_COUNT))
+            .map(|facing| sourceFacing(facing, SOURCE_COUNT, &STARCRAFT_ORIENTATION))
+            .collect();
+        for &target_count in &TARGET_COUNTS {
+            for (direction, facing) in starcraftMapping(target_count, false).iter().enumerate() {
+                let heading = FACTORIO_ORIENTATION.heading(direction as i32, target_count);
+                let error = angleBetween(facing.heading, heading).abs();
+                let best = all.iter().map(|other| angleBetween(other.heading, heading).abs()).fold(f64::INFINITY, f64::min);
+                assert!(error <= best + TIE_TOLERANCE, "{} directions, direction {}", target_count, direction);
+                assert!(error <= 90.0 / (SOURCE_COUNT - 1) as f64 + TIE_TOLERANCE);
+                assert_eq!(facing.rotation, 0.0);
+            }
+        }
+    }
+
+    #[test]
+    fn westMirrorsEast() {
+        for &target_count in &TARGET_COUNTS {
+            let facings = starcraftMapping(target_count, false);
+            for direction in 0..target_count {
+                let east = facings[direction as usize];
+                let west = facings[((target_count - direction) % target_count) as usize];
+                assert_eq!(east.source, west.source, "{} directions, direction {}", target_count, direction);
+                let on_axis = east.source == 0 || east.source == SOURCE_COUNT - 1;
+                assert_eq!(west.mirrored, !east.mirrored && !on_axis, "{} directions, direction {}", target_count, direction);
+                assert!(angleBetween(west.heading, (360.0 - east.heading) % 360.0).abs() < 1e-9);
+            }
+        }
+    }
+
+    #[test]
+    fn tiesBreakTowardsTheFirstSourceFacing() {
+        // 64 directions fall exactly between two source facings every other step
+        let facings = starcraftMapping(64, false);
+        for direction in (1..64).step_by(2) {
+            let facing = facings[direction];
+            let east_direction = if direction < 32 { direction } else { 64 - direction };
+            assert_eq!(facing.source, (east_direction as i32 - 1) / 2, "direction {}", direction);
+            assert_eq!(facing.mirrored, direction > 32 && facing.source != 0, "direction {}", direction);
+        }
+        // sources on the axis aren't mirrored, even though the mirrored copy is as near
+        assert!(!facings[0].mirrored);
+        assert!(!facings[32].mirrored);
+    }
+
+    #[test]
+    fn rotationTurnsTheFrameOntoTheDirection() {
+        for &target_count in &TARGET_COUNTS {
+            let plain = starcraftMapping(target_count, false);
+            let rotated = starcraftMapping(target_count, true);
+            for direction in 0..target_count as usize {
+                let heading = FACTORIO_ORIENTATION.heading(direction as i32, target_count);
+                assert_eq!(rotated[direction].source, plain[direction].source);
+                assert_eq!(rotated[direction].mirrored, plain[direction].mirrored);
+                assert!((rotated[direction].heading - heading).abs() < 1e-9);
+                let turn = angleBetween(plain[direction].heading, heading).to_radians() as f32;
+                assert!((rotated[direction].rotation - turn).abs() < 1e-6, "{} directions, direction {}", target_count, direction);
+            }
+        }
+    }
+
+    #[test]
+    fn thirtyTwoDirectionsMatchTheStepMapping() {
+        // what used_directions stepping did before: one source facing per direction,
+        // the west half mirrored
+        let step = 2 * (SOURCE_COUNT - 1) / 32;
+        for (direction, facing) in starcraftMapping(32, false).iter().enumerate() {
+            let source = direction as i32 * step;
+            let mirrored = source >= SOURCE_COUNT;
+            let source = if mirrored { 2 * (SOURCE_COUNT - 1) - source } else { source };
+            assert_eq!((facing.source, facing.mirrored), (source, mirrored), "direction {}", direction);
+        }
+    }
+
+    #[test]
+    fn singleDirectionFacesNorth() {
+        let facings = starcraftMapping(1, false);
+        assert_eq!(facings.len(), 1);
+        assert_eq!((facings[0].source, facings[0].mirrored), (0, false));
+    }
+}
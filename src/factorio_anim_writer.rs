@@ -2,354 +2,1056 @@ use std::path::Path;
 
 
 use crate::casc::CascStorage;
-use crate::sprite_config::{SpriteGroup, SpriteFormat};
+use crate::sprite_config::{SpriteGroup, SpriteFormat, CorpseSource, OutputTarget, TurretState, Composition, LayerRole, IconConfig};
+use crate::icon_writer::writeIcons;
 use std::error::Error;
 use crate::anim::Anim;
-use std::fs::{create_dir_all, File, read_dir};
-use crate::sprite_maker::{makeSprites, Preset, makeSpritesSd, FactorioSprites};
+use std::fs::{create_dir_all, File, read_dir, read_to_string};
+use crate::sprite_maker::{makeSprites, makeCorpseSprites, Preset, makeSpritesSd, FactorioSprites};
 use crate::lua;
 use crate::lua::LuaSyntax;
-use std::io::Write;
+use crate::lua_parser;
+use crate::lua_ser::{RawExp, SerializeResult, ToExp};
+use crate::prototype::{
+    Animation, RotatedAnimation, AnimationVariations, Sprite, Sprite4Way, SheetFiles, Stripe, DrawFields, BlendFields,
+};
+use std::io::{Cursor, Read, Write};
+use std::collections::{HashMap, HashSet};
+use rayon::prelude::*;
+use crate::run_config::{RunConfig, FactorioVersion};
 
 
-const modname: &str = "sc-redux";
+/// Port of `corpse_frame_sequence` in util.lua: the first frame stands in for the death
+/// animation, then every decay frame is held `ceil(60 / die_frame)` times.
+pub fn corpseFrameSequence(die_frame: i32, corpse_frame: i32) -> Vec<i32> {
+    let rep = (60 + die_frame - 1) / die_frame;
+    let mut result = vec![1];
+    for i in 1..=corpse_frame {
+        result.extend((0..rep).map(|_| i + 1));
+    }
+    result
+}
+
+/// Port of `calc_corpse_time` in util.lua: ticks a corpse of `die_frame` death frames
+/// and `corpse_frame` decay frames stays.
+pub fn corpseTime(die_frame: i32, corpse_frame: i32) -> i64 {
+    let rep = (60 + die_frame - 1) / die_frame;
+    (corpse_frame * rep + 1) as i64 * die_frame as i64
+}
+
+/// Frame sequence of a format, for a corpse the death frames played through once followed
+/// by the held decay frames.
+fn frameSequence(format: &SpriteFormat) -> Option<Vec<i32>> {
+    match &format.corpse {
+        Some(corpse) => {
+            let die_frame = format.animation_length;
+            Some(
+                (1..=die_frame)
+                    .chain(corpseFrameSequence(die_frame, corpse.animation_length)
+                        .into_iter()
+                        .skip(1)
+                        .map(|frame| frame + die_frame - 1))
+                    .collect()
+            )
+        }
+        None => format.frame_sequence.clone(),
+    }
+}
+
+/// Name of the corpse prototype of a format with decay frames.
+pub fn corpseName(category: &str, format_name: &str) -> String {
+    format!("{}-{}-corpse", category, format_name)
+}
 
 enum Resolution {
     HD,
     SD,
 }
 
+fn resolutionFolder<'a>(resolution: &Resolution, config: &'a RunConfig) -> &'a str {
+    match resolution {
+        Resolution::HD => &config.hd_folder,
+        Resolution::SD => &config.sd_folder,
+    }
+}
+
+/// The prototype a format is written as.
+#[derive(Clone)]
+enum SheetPrototype {
+    rotatedAnimation(Box<RotatedAnimation>),
+    sprite4Way(Box<Sprite4Way>),
+    /// A `RotatedAnimation` split into one animation per direction.
+    animationVariations(AnimationVariations),
+}
+
+impl ToExp for SheetPrototype {
+    fn toExp(&self) -> SerializeResult {
+        match self {
+            SheetPrototype::rotatedAnimation(animation) => animation.toExp(),
+            SheetPrototype::sprite4Way(sprite) => sprite.toExp(),
+            SheetPrototype::animationVariations(variations) => variations.toExp(),
+        }
+    }
+}
+
+impl SheetPrototype {
+    fn validate(&self) -> Result<(), Box<dyn Error + Send + Sync>> {
+        match self {
+            SheetPrototype::rotatedAnimation(animation) => animation.validate(),
+            SheetPrototype::sprite4Way(sprite) => sprite.validate(),
+            SheetPrototype::animationVariations(variations) => variations.validate(),
+        }
+    }
+}
+
 fn writeAnimation(
     group: &String,
     format: &SpriteFormat,
     sprites: &FactorioSprites,
     preset: &Preset,
     resolution: &Resolution,
-    output_dir: &String,
-) -> Result<lua::Exp, Box<dyn Error>> {
-    let sprite_dir = Path::new(&format!("{}/graphics", output_dir))
-        .join(match resolution {
-            Resolution::HD => "hd",
-            Resolution::SD => "sd"
-        })
-        .join(group);
-    create_dir_all(&sprite_dir)?;
-    let filename_field = if sprites.images.len() > 1 {
-        (
-            String::from("filenames"),
-            lua::Exp::Array {
-                member_list: (0..sprites.images.len())
-                    .map(|i| {
-                        let filename = match preset {
-                            Preset::normal => format!("{}-{:02}.png", &format.name, i + 1),
-                            Preset::mask => format!("{}-mask-{:02}.png", &format.name, i + 1),
-                            Preset::light => format!("{}-light-{:02}.png", &format.name, i + 1),
-                        };
-                        sprites.images[i].save(sprite_dir.join(&filename)).unwrap();
-                        lua::Exp::String(
-                            match resolution {
-                                Resolution::HD => format!("__{}__/graphics/hd/{}/{}", modname, group, &filename),
-                                Resolution::SD => format!("__{}__/graphics/sd/{}/{}", modname, group, &filename),
-                            }
-                        )
-                    })
-                    .collect()
-            }
-        )
+    config: &RunConfig,
+) -> Result<SheetPrototype, Box<dyn Error + Send + Sync>> {
+    let sheet_path = |filename: &String| config.sheetPath(resolutionFolder(resolution, config), group, filename);
+    let filenames: Vec<String> = if sprites.stripes.is_some() || sprites.images.len() > 1 {
+        (0..sprites.images.len())
+            .map(|i| match preset {
+                Preset::normal => format!("{}-{:02}.png", &format.name, i + 1),
+                Preset::mask => format!("{}-mask-{:02}.png", &format.name, i + 1),
+                Preset::light => format!("{}-light-{:02}.png", &format.name, i + 1),
+            })
+            .collect()
     } else {
-        (
-            String::from("filename"),
-            {
-                let filename = match preset {
-                    Preset::normal => format!("{}.png", &format.name),
-                    Preset::mask => format!("{}-mask.png", &format.name),
-                    Preset::light => format!("{}-light.png", &format.name),
-                };
-                sprites.images[0].save(sprite_dir.join(&filename)).unwrap();
-                lua::Exp::String(
-                    match resolution {
-                        Resolution::HD => format!("__{}__/graphics/hd/{}/{}", modname, group, &filename),
-                        Resolution::SD => format!("__{}__/graphics/sd/{}/{}", modname, group, &filename),
-                    }
-                )
+        vec![
+            match preset {
+                Preset::normal => format!("{}.png", &format.name),
+                Preset::mask => format!("{}-mask.png", &format.name),
+                Preset::light => format!("{}-light.png", &format.name),
             }
-        )
+        ]
     };
-    let mut result = lua::Exp::Table {
-        field_list: vec![
-            filename_field,
-            (
-                String::from("slice"),
-                lua::Exp::Number(sprites.slice as f32)
-            ),
-            (
-                String::from("line_length"),
-                lua::Exp::Number(sprites.slice as f32)
-            ),
-            (
-                String::from("lines_per_file"),
-                lua::Exp::Number(sprites.lines_per_file as f32)
-            ),
-            (
-                String::from("width"),
-                lua::Exp::Number(sprites.width as f32)
-            ),
-            (
-                String::from("height"),
-                lua::Exp::Number(sprites.height as f32)
-            ),
-            (
-                String::from("frame_count"),
-                lua::Exp::Number(format.animation_length as f32)
-            ),
-            (
-                String::from("direction_count"),
-                lua::Exp::Number(format.direction_count as f32)
-            ),
-            (
-                String::from("shift"),
-                lua::Exp::Array {
-                    member_list: vec![
-                        lua::Exp::Number(format.final_offset.0),
-                        lua::Exp::Number(format.final_offset.1),
-                    ]
-                }
-            ),
-            (
-                String::from("animation_speed"),
-                lua::Exp::Number(0.4)
-            ),
-            (
-                String::from("draw_as_shadow"),
-                lua::Exp::Bool(format.draw_as_shadow),
-            ),
-            (
-                String::from("run_mode"),
-                lua::Exp::String(format.run_mode.clone()),
-            ),
-            (
-                String::from("scale"),
-                if format.scalable {
-                    match resolution {
-                        Resolution::HD => lua::Exp::Var(String::from("0.5 * scale")),
-                        Resolution::SD => lua::Exp::Var(String::from("scale")),
-                    }
-                } else {
-                    match resolution {
-                        Resolution::HD => lua::Exp::Var(String::from("0.5")),
-                        Resolution::SD => lua::Exp::Var(String::from("1")),
+    sprites.images
+        .par_iter()
+        .zip(filenames.par_iter())
+        .try_for_each(|(image, filename)| -> Result<(), Box<dyn Error + Send + Sync>> {
+            let path = Path::new(&config.output_dir).join(sheet_path(filename));
+            create_dir_all(path.parent().unwrap())?;
+            Ok(image.save(path)?)
+        })?;
+    if !sprites.debug_images.is_empty() {
+        let debug_dir = Path::new(&config.output_dir).join("debug").join(group);
+        create_dir_all(&debug_dir)?;
+        sprites.debug_images
+            .par_iter()
+            .zip(filenames.par_iter())
+            .try_for_each(|(image, filename)| {
+                image.save(debug_dir.join(filename.replace(".png", "-debug.png")))
+            })?;
+    }
+    let lua_filename = |filename: &String| format!("__{}__/{}", config.mod_name, sheet_path(filename));
+    // half HD pixels, 64 HD pixels to a tile
+    let shiftComponent = |final_offset: f64, shift_x2: i32| {
+        let shift = lua::Rational::new(shift_x2 as i64, 128);
+        match lua::Rational::fromFloat(final_offset) {
+            Some(final_offset) => Ok(RawExp(final_offset.add(shift).toExp())),
+            None => lua::Exp::number(final_offset + shift_x2 as f64 / 128.0).map(RawExp),
+        }
+    };
+    let shift = [
+        shiftComponent(format.final_offset.0, sprites.shift_x2.0)?,
+        shiftComponent(format.final_offset.1, sprites.shift_x2.1)?,
+    ];
+    let scale = RawExp(if format.scalable {
+        match resolution {
+            Resolution::HD => lua::Exp::binop(lua::Exp::number(0.5)?, "*", lua::Exp::var("scale")?)?,
+            Resolution::SD => lua::Exp::var("scale")?,
+        }
+    } else {
+        match resolution {
+            Resolution::HD => lua::Exp::number(0.5)?,
+            Resolution::SD => lua::Exp::Integer(1),
+        }
+    });
+    let blend = match preset {
+        Preset::normal => BlendFields {
+            draw_as_glow: Some(format.draw_as_glow),
+            ..BlendFields::default()
+        },
+        Preset::mask => BlendFields {
+            flags: Some(vec![String::from("mask")]),
+            tint: Some(RawExp(lua::Exp::var("tint")?)),
+            ..BlendFields::default()
+        },
+        Preset::light => BlendFields {
+            draw_as_glow: Some(true),
+            flags: Some(vec![String::from("light")]),
+            tint: None,
+        },
+    };
+    if format.target == OutputTarget::sprite4Way {
+        let sprite = |direction: i32| -> Result<Sprite, Box<dyn Error + Send + Sync>> {
+            let frame_idx = direction * format.framesPerDirection() + format.empty_pad;
+            let frame = sprites.frameRect(frame_idx);
+            Ok(Sprite {
+                filename: lua_filename(&filenames[frame.img_idx]),
+                x: frame.x,
+                y: frame.y,
+                width: frame.width,
+                height: frame.height,
+                shift: [
+                    shiftComponent(format.final_offset.0, frame.shift_x2.0)?,
+                    shiftComponent(format.final_offset.1, frame.shift_x2.1)?,
+                ],
+                draw_as_shadow: format.draw_as_shadow,
+                scale: scale.clone(),
+                blend: blend.clone(),
+                hr_version: None,
+            })
+        };
+        return Ok(SheetPrototype::sprite4Way(Box::new(Sprite4Way {
+            north: sprite(0)?,
+            east: sprite(1)?,
+            south: sprite(2)?,
+            west: sprite(3)?,
+        })));
+    }
+    let (filename, lua_filenames, stripes) = if let Some(stripes) = &sprites.stripes {
+        let stripes = stripes.iter()
+            .zip(&filenames)
+            .map(|(stripe, filename)| Stripe {
+                filename: lua_filename(filename),
+                width_in_frames: stripe.width_in_frames,
+                height_in_frames: stripe.height_in_frames,
+            })
+            .collect();
+        (None, None, Some(stripes))
+    } else if filenames.len() > 1 {
+        (None, Some(filenames.iter().map(lua_filename).collect()), None)
+    } else {
+        (Some(lua_filename(&filenames[0])), None, None)
+    };
+    let lines = |value: i32| if sprites.stripes.is_none() { Some(value) } else { None };
+    // lines_per_file only counts for `filenames`
+    let split_files = lua_filenames.is_some();
+    // a RotatedSprite has one frame per direction and nothing to animate
+    let animated = format.target != OutputTarget::rotatedSprite;
+    Ok(SheetPrototype::rotatedAnimation(Box::new(RotatedAnimation {
+        files: SheetFiles {
+            filename,
+            filenames: lua_filenames,
+            stripes,
+            slice: lines(sprites.slice),
+            line_length: lines(sprites.slice),
+            lines_per_file: if split_files { Some(sprites.lines_per_file) } else { None },
+            width: sprites.width,
+            height: sprites.height,
+            frame_count: if animated { Some(format.framesPerDirection()) } else { None },
+        },
+        direction_count: format.direction_count,
+        draw: DrawFields {
+            shift,
+            animation_speed: if animated { Some(format.timing.animationSpeed()) } else { None },
+            draw_as_shadow: format.draw_as_shadow,
+            run_mode: if animated { Some(format.run_mode.clone()) } else { None },
+            scale,
+            frame_sequence: if animated { frameSequence(format) } else { None },
+            blend,
+        },
+        hr_version: None,
+    })))
+}
+
+/// Adds `hd` as the `hr_version` of `sd`, per direction for a `Sprite4Way`.
+fn attachHrVersion(sd: &mut SheetPrototype, hd: SheetPrototype) {
+    match (sd, hd) {
+        (SheetPrototype::rotatedAnimation(sd), SheetPrototype::rotatedAnimation(hd)) => {
+            sd.hr_version = Some(hd);
+        }
+        (SheetPrototype::sprite4Way(sd), SheetPrototype::sprite4Way(hd)) => {
+            sd.north.hr_version = Some(Box::new(hd.north));
+            sd.east.hr_version = Some(Box::new(hd.east));
+            sd.south.hr_version = Some(Box::new(hd.south));
+            sd.west.hr_version = Some(Box::new(hd.west));
+        }
+        _ => unreachable!("both resolutions are written as the same prototype"),
+    }
+}
+
+/// Writes the HD sheets and, for Factorio 1.1, the SD sheets downscaled from them, which
+/// then carry the HD prototype as `hr_version`.
+fn writeResolutions(
+    category: &String,
+    format: &SpriteFormat,
+    hd_sprites: &FactorioSprites,
+    preset: &Preset,
+    config: &RunConfig,
+) -> Result<SheetPrototype, Box<dyn Error + Send + Sync>> {
+    let hr_prototype = writeAnimation(category, format, hd_sprites, preset, &Resolution::HD, config)?;
+    if config.factorio_version == FactorioVersion::v2_0 {
+        return Ok(hr_prototype);
+    }
+    let sd_sprites = makeSpritesSd(hd_sprites, format, &config.sd_limits, config.sd_filters.forPreset(*preset))?;
+    let mut prototype = writeAnimation(category, format, &sd_sprites, preset, &Resolution::SD, config)?;
+    attachHrVersion(&mut prototype, hr_prototype);
+    Ok(prototype)
+}
+
+fn writeFormat(
+    sprite_group: &SpriteGroup,
+    anim: &Anim,
+    decay_anims: &HashMap<String, Anim>,
+    format: &SpriteFormat,
+    preset: &Preset,
+    config: &RunConfig,
+) -> Result<Option<lua::Exp>, Box<dyn Error + Send + Sync>> {
+    match format.target {
+        OutputTarget::rotatedAnimation => {}
+        _ if format.split_anim => {
+            return Err(format!("{}: split_anim only works for RotatedAnimation", format.name).into());
+        }
+        OutputTarget::rotatedSprite if format.animation_length != 1 || format.empty_pad != 0 => {
+            return Err(format!("{}: a RotatedSprite takes exactly one frame per direction", format.name).into());
+        }
+        OutputTarget::sprite4Way if format.direction_count != 4 || format.animation_length != 1 => {
+            return Err(format!("{}: a Sprite4Way needs 4 directions of one frame", format.name).into());
+        }
+        _ => {}
+    }
+    if !format.timing.isValid() {
+        return Err(format!("{}: frame timings must be positive", format.name).into());
+    }
+    if format.corpse.is_some() && (format.frame_sequence.is_some() || format.empty_pad != 0) {
+        return Err(format!("{}: a corpse makes its own frame sequence and needs no padding", format.name).into());
+    }
+    let tmp = match &format.corpse {
+        Some(corpse) => makeCorpseSprites(
+            anim,
+            &decay_anims[&corpse.source],
+            format,
+            sprite_group.base_offset_x2,
+            *preset,
+            &config.hd_limits,
+            config.debug_overlay,
+        )?,
+        None => makeSprites(
+            anim,
+            format,
+            sprite_group.base_offset_x2,
+            *preset,
+            &config.hd_limits,
+            config.debug_overlay,
+        )?,
+    };
+    let hd_sprites = match tmp {
+        Some(sprites) => sprites,
+        None => return Ok(None),
+    };
+    let prototype = if format.split_anim {
+        SheetPrototype::animationVariations(AnimationVariations(
+            (0..format.direction_count)
+                .into_par_iter()
+                .map(|i| {
+                    let split_format = SpriteFormat {
+                        direction_count: 1,
+                        name: format!("{}-{:02}", format.name, i + 1),
+                        ..format.clone()
+                    };
+                    let split_hd_sprites = FactorioSprites {
+                        images: vec![hd_sprites.images[i as usize].clone()],
+                        debug_images: hd_sprites.debug_images.get(i as usize).cloned().into_iter().collect(),
+                        ..hd_sprites.clone()
+                    };
+                    match writeResolutions(&sprite_group.category, &split_format, &split_hd_sprites, preset, config)? {
+                        SheetPrototype::rotatedAnimation(animation) => Ok(Animation::from(*animation)),
+                        _ => unreachable!("only a RotatedAnimation is split"),
                     }
-                }
-            ),
-        ]
+                })
+                .collect::<Result<_, Box<dyn Error + Send + Sync>>>()?
+        ))
+    } else {
+        writeResolutions(&sprite_group.category, format, &hd_sprites, preset, config)?
+    };
+    prototype.validate().map_err(|err| format!("{}: {}", format.name, err))?;
+    Ok(Some(prototype.toExp()?))
+}
+
+/// Lua of one format and preset of a sprite group.
+struct FormatOutput<'a> {
+    category: &'a String,
+    format: &'a SpriteFormat,
+    preset: Preset,
+    sprites: lua::Exp,
+}
+
+fn luaFunction(par_list: Vec<String>, exp: lua::Exp) -> lua::Exp {
+    lua::Exp::Function {
+        par_list,
+        body: lua::Block {
+            stats: Vec::new(),
+            last_stat: Some(lua::LastStat::Return { exp_list: vec![exp] }),
+        },
+    }
+}
+
+fn animationEntry(output: &FormatOutput) -> (String, lua::Exp) {
+    let anim_name = match output.preset {
+        Preset::normal => format!("{}_{}", output.category, output.format.name),
+        Preset::mask => format!("{}_{}_mask", output.category, output.format.name),
+        Preset::light => format!("{}_{}_light", output.category, output.format.name),
     };
-    if let lua::Exp::Table { field_list } = &mut result {
-        if let Some(frame_sequence) = &format.frame_sequence {
-            field_list.push(
-                (
-                    String::from("frame_sequence"),
-                    lua::Exp::Array {
-                        member_list: frame_sequence
-                            .into_iter()
-                            .map(|x| lua::Exp::Number(*x as f32))
-                            .collect()
+    let mut params = Vec::new();
+    if output.format.scalable { params.push(String::from("scale")); }
+    if let Preset::mask = output.preset { params.push(String::from("tint")); }
+    (anim_name.replace("-", "_"), luaFunction(params, output.sprites.clone()))
+}
+
+/// One `{category}_turret` entry per category with turret formats, returning its
+/// animation sets with every format and preset of a state as a layer.
+fn turretEntries(outputs: &[FormatOutput]) -> Vec<(String, lua::Exp)> {
+    let mut categories: Vec<&String> = Vec::new();
+    for output in outputs {
+        if let OutputTarget::turret(_) = output.format.target {
+            if !categories.contains(&output.category) {
+                categories.push(output.category);
+            }
+        }
+    }
+    categories.into_iter()
+        .map(|category| {
+            let layers: Vec<(TurretState, &FormatOutput)> = outputs.iter()
+                .filter(|output| output.category == category)
+                .filter_map(|output| match output.format.target {
+                    OutputTarget::turret(state) => Some((state, output)),
+                    _ => None,
+                })
+                .collect();
+            let mut params = Vec::new();
+            if layers.iter().any(|(_, output)| output.format.scalable) {
+                params.push(String::from("scale"));
+            }
+            if layers.iter().any(|(_, output)| output.preset == Preset::mask) {
+                params.push(String::from("tint"));
+            }
+            let field_list = TurretState::ALL.iter()
+                .filter(|state| layers.iter().any(|(layer_state, _)| layer_state == *state))
+                .map(|state| (
+                    String::from(state.key()),
+                    lua::Exp::Table {
+                        field_list: vec![
+                            (
+                                String::from("layers"),
+                                lua::Exp::Array {
+                                    member_list: layers.iter()
+                                        .filter(|(layer_state, _)| layer_state == state)
+                                        .map(|(_, output)| output.sprites.clone())
+                                        .collect()
+                                }
+                            ),
+                        ]
                     }
-                )
+                ))
+                .collect();
+            (
+                format!("{}_turret", category).replace("-", "_"),
+                luaFunction(params, lua::Exp::Table { field_list }),
             )
+        })
+        .collect()
+}
+
+/// One `{category}_{format}_corpse` entry per format with decay frames, returning its
+/// corpse prototype with every preset as a layer.
+fn corpseEntries(outputs: &[FormatOutput]) -> Vec<(String, lua::Exp)> {
+    let mut corpses: Vec<(&String, &SpriteFormat, &CorpseSource)> = Vec::new();
+    for output in outputs {
+        if let Some(corpse) = &output.format.corpse {
+            if !corpses.iter().any(|(category, format, _)| *category == output.category && format.name == output.format.name) {
+                corpses.push((output.category, output.format, corpse));
+            }
         }
     }
-    if let lua::Exp::Table { field_list } = &mut result {
-        match preset {
-            Preset::normal => {
-                field_list.push(
-                    (
-                        String::from("draw_as_glow"),
-                        lua::Exp::Bool(format.draw_as_glow),
-                    )
-                );
+    corpses.into_iter()
+        .map(|(category, format, corpse)| {
+            let layers: Vec<&FormatOutput> = outputs.iter()
+                .filter(|output| output.category == category && output.format.name == format.name)
+                .collect();
+            let mut params = Vec::new();
+            if format.scalable { params.push(String::from("scale")); }
+            if layers.iter().any(|output| output.preset == Preset::mask) {
+                params.push(String::from("tint"));
             }
-            Preset::mask => {
-                field_list.push(
+            let animation = if layers.len() == 1 {
+                layers[0].sprites.clone()
+            } else {
+                lua::Exp::Table {
+                    field_list: vec![
+                        (
+                            String::from("layers"),
+                            lua::Exp::Array {
+                                member_list: layers.iter().map(|output| output.sprites.clone()).collect()
+                            }
+                        ),
+                    ]
+                }
+            };
+            let prototype = lua::Exp::Table {
+                field_list: vec![
+                    (
+                        String::from("type"),
+                        lua::Exp::String(String::from("corpse")),
+                    ),
+                    (
+                        String::from("name"),
+                        lua::Exp::String(corpseName(category, &format.name)),
+                    ),
                     (
                         String::from("flags"),
                         lua::Exp::Array {
-                            member_list: vec![lua::Exp::String(String::from("mask"))]
+                            member_list: vec![
+                                lua::Exp::String(String::from("placeable-neutral")),
+                                lua::Exp::String(String::from("placeable-off-grid")),
+                                lua::Exp::String(String::from("not-on-map")),
+                            ]
                         },
-                    )
-                );
-                field_list.push(
+                    ),
                     (
-                        String::from("tint"),
-                        lua::Exp::Var(String::from("tint")),
-                    )
-                );
-            }
-            Preset::light => {
-                field_list.push(
+                        String::from("subgroup"),
+                        lua::Exp::String(String::from("corpses")),
+                    ),
                     (
-                        String::from("draw_as_glow"),
-                        lua::Exp::Bool(true),
-                    )
-                );
-                field_list.push(
+                        String::from("selectable_in_game"),
+                        lua::Exp::Bool(false),
+                    ),
                     (
-                        String::from("flags"),
-                        lua::Exp::Array {
-                            member_list: vec![lua::Exp::String(String::from("light"))]
-                        },
-                    )
-                );
+                        String::from("time_before_removed"),
+                        lua::Exp::Integer(corpseTime(format.animation_length, corpse.animation_length)),
+                    ),
+                    (
+                        String::from("final_render_layer"),
+                        lua::Exp::String(String::from("corpse")),
+                    ),
+                    (
+                        String::from("animation"),
+                        animation,
+                    ),
+                ]
+            };
+            (
+                format!("{}_{}_corpse", category, format.name).replace("-", "_"),
+                luaFunction(params, prototype),
+            )
+        })
+        .collect()
+}
+
+/// Frames a format plays through once, which Factorio wants equal across layers.
+fn playedFrameCount(format: &SpriteFormat) -> usize {
+    match (&format.target, frameSequence(format)) {
+        (OutputTarget::rotatedSprite, _) => 1,
+        (_, Some(frame_sequence)) => frame_sequence.len(),
+        (_, None) => format.framesPerDirection() as usize,
+    }
+}
+
+/// One entry per composition, returning its layers. A composition with a layer that
+/// produced no output is left out with a warning, mismatched layers are an error.
+fn compositionEntries(
+    compositions: &[Composition],
+    outputs: &[FormatOutput],
+) -> Result<Vec<(String, lua::Exp)>, Box<dyn Error + Send + Sync>> {
+    let mut entries = Vec::new();
+    'compositions: for composition in compositions {
+        let mut layers: Vec<&FormatOutput> = Vec::new();
+        for layer in &composition.layers {
+            let preset = match layer.role {
+                LayerRole::mask => Preset::mask,
+                LayerRole::light => Preset::light,
+                LayerRole::body | LayerRole::shadow | LayerRole::overlay => Preset::normal,
+            };
+            let output = outputs.iter().find(|output| {
+                output.category == &layer.category && output.format.name == layer.format && output.preset == preset
+            });
+            let output = match output {
+                Some(output) => output,
+                None => {
+                    println!(
+                        "  {}: no {:?} layer from {} {}, skipping",
+                        composition.name, layer.role, layer.category, layer.format,
+                    );
+                    continue 'compositions;
+                }
+            };
+            if output.format.draw_as_shadow != (layer.role == LayerRole::shadow) {
+                return Err(format!(
+                    "{}: {} {} is{} drawn as shadow but used as {:?} layer",
+                    composition.name,
+                    layer.category,
+                    layer.format,
+                    if output.format.draw_as_shadow { "" } else { " not" },
+                    layer.role,
+                ).into());
+            }
+            match output.format.target {
+                OutputTarget::rotatedAnimation | OutputTarget::rotatedSprite if !output.format.split_anim => {}
+                _ => return Err(format!(
+                    "{}: {} {} can not be a layer, only unsplit rotated animations and sprites can",
+                    composition.name, layer.category, layer.format,
+                ).into()),
             }
+            layers.push(output);
         }
+        let first = match layers.first() {
+            Some(first) => first.format,
+            None => continue,
+        };
+        for layer in &layers[1..] {
+            if layer.format.target != first.target
+                || layer.format.direction_count != first.direction_count
+                || playedFrameCount(layer.format) != playedFrameCount(first) {
+                return Err(format!(
+                    "{}: {} has {} directions of {} frames, but {} has {} directions of {} frames",
+                    composition.name,
+                    layer.format.name,
+                    layer.format.direction_count,
+                    playedFrameCount(layer.format),
+                    first.name,
+                    first.direction_count,
+                    playedFrameCount(first),
+                ).into());
+            }
+            if layer.format.timing.animationSpeed() != first.timing.animationSpeed() {
+                return Err(format!(
+                    "{}: {} and {} play at different speeds",
+                    composition.name, layer.format.name, first.name,
+                ).into());
+            }
+        }
+        let mut params = Vec::new();
+        if layers.iter().any(|output| output.format.scalable) {
+            params.push(String::from("scale"));
+        }
+        if layers.iter().any(|output| output.preset == Preset::mask) {
+            params.push(String::from("tint"));
+        }
+        entries.push((
+            composition.name.replace("-", "_"),
+            luaFunction(params, lua::Exp::Table {
+                field_list: vec![
+                    (
+                        String::from("layers"),
+                        lua::Exp::Array {
+                            member_list: layers.iter().map(|output| output.sprites.clone()).collect()
+                        }
+                    ),
+                ]
+            }),
+        ));
     }
-    Ok(result)
+    Ok(entries)
 }
 
+/// Decodes the anim of a group and writes all its formats and presets, in config order.
+fn writeSpriteGroup<'a>(
+    sprite_group: &'a SpriteGroup,
+    anim_data: &[u8],
+    decay_data: &HashMap<String, Vec<u8>>,
+    config: &RunConfig,
+) -> Result<Vec<FormatOutput<'a>>, Box<dyn Error + Send + Sync>> {
+    println!("Processing: {} ({})", sprite_group.source, sprite_group.category);
+    let anim = Anim::fromFile(Cursor::new(anim_data))?;
+    let mut decay_anims = HashMap::new();
+    for (source, data) in decay_data {
+        decay_anims.insert(source.clone(), Anim::fromFile(Cursor::new(data))?);
+    }
+
+    let presets = [Preset::normal, Preset::mask, Preset::light];
+    let jobs: Vec<(&SpriteFormat, &Preset)> = sprite_group.sprites.iter()
+        .flat_map(|format| presets.iter().map(move |preset| (format, preset)))
+        .collect();
+    let outputs = jobs
+        .into_par_iter()
+        .map(|(format, preset)| {
+            Ok(writeFormat(sprite_group, &anim, &decay_anims, format, preset, config)?
+                .map(|sprites| FormatOutput {
+                    category: &sprite_group.category,
+                    format,
+                    preset: *preset,
+                    sprites,
+                }))
+        })
+        .collect::<Result<Vec<_>, Box<dyn Error + Send + Sync>>>()?;
+    Ok(outputs.into_iter().flatten().collect())
+}
+
+/// Whether every format of `sprite_group` already has HD sheets in the output folder,
+/// which debug builds take as a reason to skip the group.
+fn hdSheetsExist(sprite_group: &SpriteGroup, config: &RunConfig) -> Result<bool, Box<dyn Error + Send + Sync>> {
+    let folder_name = Path::new(&config.output_dir)
+        .join(config.sheetPath(&config.hd_folder, &sprite_group.category, ""));
+    create_dir_all(&folder_name)?;
+    let mut file_names = Vec::new();
+    for file in read_dir(&folder_name)? {
+        file_names.push(file?.file_name().to_string_lossy().into_owned());
+    }
+    Ok(sprite_group.sprites.iter()
+        .all(|sprite| file_names.iter().any(|file_name| file_name.starts_with(&sprite.name))))
+}
+
+/// Writes the sheets and `anim.lua`, returning the entries of its table.
 pub fn writeAnimations(
     storage: &mut CascStorage,
     metadata: &Vec<SpriteGroup>,
-    output_dir: &String,
-) -> Result<(), Box<dyn Error>> {
-    let mut return_table = Vec::new();
+    compositions: &[Composition],
+    icons: &[IconConfig],
+    config: &RunConfig,
+) -> Result<Vec<(String, lua::Exp)>, Box<dyn Error + Send + Sync>> {
+    let output_dir = &config.output_dir;
 
+    // CASC reads stay on this thread, everything after them fans out
+    let mut sources = Vec::new();
     for sprite_group in metadata {
-        println!("Processing: {} ({})", sprite_group.source, sprite_group.category);
-        if cfg!(debug_assertions) {
-            let folder_name = format!("{}/graphics/hd/{}", output_dir, sprite_group.category);
-            if !Path::new(&folder_name).exists() {
-                create_dir_all(&folder_name)?;
+        if cfg!(debug_assertions) && hdSheetsExist(sprite_group, config)? {
+            // pruning would drop the entries of the skipped group and what is layered on them
+            if config.prune_entries {
+                return Err(format!(
+                    "--prune needs every group written, but debug builds skip {} as its sheets exist",
+                    sprite_group.source,
+                ).into());
             }
-            if sprite_group.sprites.iter()
-                .all(|sprite| read_dir(&folder_name)
-                    .unwrap()
-                    .any(|file| file.as_ref()
-                        .unwrap()
-                        .path()
-                        .file_name()
-                        .unwrap()
-                        .to_string_lossy()
-                        .starts_with(&sprite.name)
-                    )
-                ) {
-                continue;
+            continue;
+        }
+        let mut anim_data = Vec::new();
+        storage.openFile(&sprite_group.source)?.read_to_end(&mut anim_data)?;
+        let mut decay_data = HashMap::new();
+        for corpse in sprite_group.sprites.iter().filter_map(|format| format.corpse.as_ref()) {
+            if !decay_data.contains_key(&corpse.source) {
+                let mut data = Vec::new();
+                storage.openFile(&corpse.source)?.read_to_end(&mut data)?;
+                decay_data.insert(corpse.source.clone(), data);
             }
         }
-        let anim = Anim::fromFile(
-            storage.openFile(&sprite_group.source)?
-        )?;
-
-        let presets = vec![Preset::normal, Preset::mask, Preset::light];
-        for format in &sprite_group.sprites {
-            for preset in &presets {
-                let tmp = makeSprites(&anim, format, sprite_group.base_offset_x2, preset.clone())?;
-                let hd_sprites;
-                match tmp {
-                    Some(sprites) => hd_sprites = sprites,
-                    None => { continue; }
-                }
-                let lua_sprites = if format.split_anim {
-                    lua::Exp::Array {
-                        member_list: (0..format.direction_count)
-                            .map(|i| {
-                                let split_format = SpriteFormat {
-                                    direction_count: 1,
-                                    name: format!("{}-{:02}", format.name, i + 1),
-                                    ..format.clone()
-                                };
-                                let split_hd_sprites = FactorioSprites {
-                                    images: vec![hd_sprites.images[i as usize].clone()],
-                                    ..hd_sprites.clone()
-                                };
-                                let lua_hr_sprites = writeAnimation(
-                                    &sprite_group.category,
-                                    &split_format,
-                                    &split_hd_sprites,
-                                    preset,
-                                    &Resolution::HD,
-                                    &output_dir,
-                                ).unwrap();
-                                let sd_sprites = makeSpritesSd(&split_hd_sprites, &split_format);
-                                let mut lua_sprites = writeAnimation(
-                                    &sprite_group.category,
-                                    &split_format,
-                                    &sd_sprites,
-                                    preset,
-                                    &Resolution::SD,
-                                    &output_dir,
-                                ).unwrap();
-                                if let lua::Exp::Table { field_list } = &mut lua_sprites {
-                                    field_list.push(
-                                        (
-                                            String::from("hr_version"),
-                                            lua_hr_sprites
-                                        )
-                                    );
-                                }
-                                lua_sprites
-                            })
-                            .collect()
-                    }
-                } else {
-                    let lua_hr_sprites = writeAnimation(
-                        &sprite_group.category,
-                        &format,
-                        &hd_sprites,
-                        preset,
-                        &Resolution::HD,
-                        &output_dir,
-                    )?;
-                    let sd_sprites = makeSpritesSd(&hd_sprites, format);
-                    let mut lua_sprites = writeAnimation(
-                        &sprite_group.category,
-                        &format,
-                        &sd_sprites,
-                        preset,
-                        &Resolution::SD,
-                        &output_dir,
-                    )?;
-                    if let lua::Exp::Table { field_list } = &mut lua_sprites {
-                        field_list.push(
-                            (
-                                String::from("hr_version"),
-                                lua_hr_sprites
-                            )
-                        );
-                    }
-                    lua_sprites
-                };
-                let anim_name = match preset {
-                    Preset::normal => format!("{}_{}", &sprite_group.category, format.name),
-                    Preset::mask => format!("{}_{}_mask", &sprite_group.category, format.name),
-                    Preset::light => format!("{}_{}_light", &sprite_group.category, format.name),
-                };
-                let anim_name = anim_name.replace("-", "_");
-                let mut params = Vec::new();
-                if format.scalable { params.push(String::from("scale")); }
-                if let Preset::mask = preset { params.push(String::from("tint")); }
-                return_table.push(
-                    (
-                        anim_name,
-                        lua::Exp::Function {
-                            par_list: params,
-                            body: lua::Block {
-                                stats: Vec::new(),
-                                last_stat: Some(lua::LastStat::Return { exp_list: vec![lua_sprites] }),
-                            },
+        sources.push((sprite_group, anim_data, decay_data));
+    }
+
+    let outputs: Vec<FormatOutput> = sources
+        .par_iter()
+        .map(|(sprite_group, anim_data, decay_data)| writeSpriteGroup(sprite_group, anim_data, decay_data, config))
+        .collect::<Result<Vec<_>, _>>()?
+        .into_iter()
+        .flatten()
+        .collect();
+    let mut return_table: Vec<(String, lua::Exp)> = outputs.iter().map(animationEntry).collect();
+    return_table.extend(turretEntries(&outputs));
+    return_table.extend(corpseEntries(&outputs));
+    // kept apart, composition names are free to match the format entries they stitch
+    return_table.push((
+        String::from("layered"),
+        lua::Exp::Table { field_list: compositionEntries(compositions, &outputs)? },
+    ));
+    return_table.push((String::from("icons"), writeIcons(storage, icons, config)?));
+
+    let anim_path = format!("{}/anim.lua", output_dir);
+    let mut stats = Vec::new();
+    if Path::new(&anim_path).exists() {
+        let (existing_stats, existing_entries) = readAnimEntries(&anim_path)?;
+        stats = existing_stats;
+        return_table = mergeEntries(return_table, existing_entries, "anim", config.prune_entries);
+    }
+    reportUnreferenced(&return_table, output_dir)?;
+
+    let mut file = File::create(&anim_path)?;
+    let exp = lua::Exp::Table { field_list: return_table.clone() };
+    file.write_all(lua::wrap(stats, exp, lua::Wrapper::module).print(&config.lua_style).as_ref())?;
+    Ok(return_table)
+}
+
+/// Statements of an `anim.lua` and the entries of the table it returns.
+type AnimEntries = (Vec<lua::Stat>, Vec<(String, lua::Exp)>);
+
+/// The statements and returned table of an existing `anim.lua`.
+fn readAnimEntries(path: &str) -> Result<AnimEntries, Box<dyn Error + Send + Sync>> {
+    let block = lua_parser::parse(&read_to_string(path)?)
+        .map_err(|err| format!("{}: {}", path, err))?;
+    match block.last_stat {
+        Some(lua::LastStat::Return { mut exp_list }) if exp_list.len() == 1 => match exp_list.pop() {
+            Some(lua::Exp::Table { field_list }) => Ok((block.stats, field_list)),
+            _ => Err(format!("{}: doesn't return a table of animations, move it out of the way", path).into()),
+        },
+        _ => Err(format!("{}: doesn't return a table of animations, move it out of the way", path).into()),
+    }
+}
+
+/// `generated` with the entries only `existing` has added after it, such as hand written
+/// ones or those of groups skipped this run. With `prune` those are dropped instead, which
+/// clears out entries of formats no longer generated. Nested tables are merged the same
+/// way, `path` naming the table in messages.
+fn mergeEntries(
+    generated: Vec<(String, lua::Exp)>,
+    existing: Vec<(String, lua::Exp)>,
+    path: &str,
+    prune: bool,
+) -> Vec<(String, lua::Exp)> {
+    let mut existing: Vec<Option<(String, lua::Exp)>> = existing.into_iter().map(Some).collect();
+    let mut merged: Vec<(String, lua::Exp)> = generated.into_iter()
+        .map(|(key, exp)| {
+            let old = existing.iter_mut()
+                .find(|entry| matches!(entry, Some((old_key, _)) if *old_key == key))
+                .and_then(|entry| entry.take());
+            match (exp, old) {
+                (lua::Exp::Table { field_list }, Some((_, lua::Exp::Table { field_list: old_list }))) => {
+                    let field_list = mergeEntries(field_list, old_list, &format!("{}.{}", path, key), prune);
+                    (key, lua::Exp::Table { field_list })
+                },
+                (exp, _) => (key, exp),
+            }
+        })
+        .collect();
+    for (key, exp) in existing.into_iter().flatten() {
+        if prune {
+            println!("Pruned {}.{}", path, key);
+        } else {
+            merged.push((key, exp));
+        }
+    }
+    merged
+}
+
+/// Prints the animations no other Lua file in the output folder uses through
+/// `require("anim")`. Nothing is printed when there are no such files to go by.
+fn reportUnreferenced(
+    entries: &[(String, lua::Exp)],
+    output_dir: &str,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let mut referenced = HashSet::new();
+    let mut checked_files = 0;
+    for file in read_dir(output_dir)? {
+        let path = file?.path();
+        if path.extension().map_or(true, |extension| extension != "lua")
+            || path.file_name().is_some_and(|name| name == "anim.lua") {
+            continue;
+        }
+        let block = match lua_parser::parse(&read_to_string(&path)?) {
+            Ok(block) => block,
+            Err(err) => {
+                println!("Warning: skipping {} when looking for unused animations: {}", path.display(), err);
+                continue;
+            },
+        };
+        checked_files += 1;
+        let mut aliases = vec![String::from("anim")];
+        for stat in &block.stats {
+            if let lua::Stat::Local { name_list, exp_list } = stat {
+                for (name, exp) in name_list.iter().zip(exp_list) {
+                    if let lua::Exp::Call { function, arg_list } = exp {
+                        if matches!(&**function, lua::Exp::Var(require) if require == "require")
+                            && matches!(&arg_list[..], [lua::Exp::String(module)] if module == "anim") {
+                            aliases.push(name.clone());
                         }
-                    )
-                )
+                    }
+                }
             }
         }
+        block.walk(&mut |exp| {
+            if let lua::Exp::Field { prefix, name } = exp {
+                match &**prefix {
+                    lua::Exp::Var(var) if aliases.contains(var) => {
+                        referenced.insert(name.clone());
+                    },
+                    lua::Exp::Field { prefix, name: table } => if let lua::Exp::Var(var) = &**prefix {
+                        if aliases.contains(var) {
+                            referenced.insert(format!("{}.{}", table, name));
+                        }
+                    },
+                    _ => {},
+                }
+            }
+        });
     }
-    let mut file = File::create(format!("{}/anim.lua", output_dir))?;
-    file.write_all(
-        lua::LastStat::Return {
-            exp_list: vec![
-                lua::Exp::Table {
-                    field_list: return_table
+    if checked_files == 0 { return Ok(()); }
+
+    for (key, exp) in entries {
+        match exp {
+            lua::Exp::Function { .. } if !referenced.contains(key) => {
+                println!("Unreferenced animation: anim.{}", key);
+            },
+            lua::Exp::Table { field_list } if key == "layered" => {
+                for (name, _) in field_list {
+                    let path = format!("{}.{}", key, name);
+                    if !referenced.contains(&path) {
+                        println!("Unreferenced animation: anim.{}", path);
+                    }
                 }
-            ]
-        }.prettyPrint().as_ref()
-    )?;
+            },
+            _ => {},
+        }
+    }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sprite_maker::FrameRect;
+    use image::RgbaImage;
+
+    fn config(test: &str) -> RunConfig {
+        let output_dir = std::env::temp_dir().join(format!("factorio_sc_graphics-{}", test));
+        RunConfig {
+            output_dir: output_dir.to_string_lossy().into_owned(),
+            mod_name: String::from("test_mod"),
+            factorio_version: FactorioVersion::v1_1,
+            ..RunConfig::default()
+        }
+    }
+
+    /// One sheet of `slice` by `lines_per_file` blank 8x8 cells.
+    fn gridSprites(slice: i32, lines_per_file: i32) -> FactorioSprites {
+        FactorioSprites {
+            images: vec![RgbaImage::new(8 * slice as u32, 8 * lines_per_file as u32)],
+            slice,
+            lines_per_file,
+            stripes: None,
+            width: 8,
+            height: 8,
+            shift_x2: (0, 0),
+            debug_images: Vec::new(),
+            frames: None,
+        }
+    }
+
+    fn rotatedAnimation(prototype: SheetPrototype) -> RotatedAnimation {
+        match prototype {
+            SheetPrototype::rotatedAnimation(animation) => *animation,
+            _ => panic!("expected a RotatedAnimation"),
+        }
+    }
+
+    #[test]
+    fn corpseTimeMatchesUtilLua() {
+        // (4 * ceil(60 / 8) + 1) * 8, as calc_corpse_time(8, 4) gives
+        assert_eq!(corpseTime(8, 4), 264);
+        assert_eq!(corpseTime(7, 3), (3 * 9 + 1) * 7);
+        assert_eq!(corpseFrameSequence(8, 4).len(), 4 * 8 + 1);
+    }
+
+    #[test]
+    fn rotatedSpriteHasNoAnimationFields() {
+        let format = SpriteFormat {
+            name: String::from("idle"),
+            direction_count: 4,
+            animation_length: 1,
+            target: OutputTarget::rotatedSprite,
+            ..SpriteFormat::default()
+        };
+        let category = String::from("test");
+        let config = config("rotated-sprite");
+        let prototype = writeResolutions(&category, &format, &gridSprites(2, 2), &Preset::normal, &config).unwrap();
+        prototype.validate().unwrap();
+        let sprite = rotatedAnimation(prototype);
+        for sprite in [&sprite, sprite.hr_version.as_deref().unwrap()] {
+            assert_eq!(sprite.direction_count, 4);
+            assert_eq!(sprite.files.frame_count, None);
+            assert_eq!(sprite.draw.animation_speed, None);
+            assert_eq!(sprite.draw.run_mode, None);
+            assert!(sprite.draw.frame_sequence.is_none());
+        }
+        assert_eq!(sprite.files.line_length, Some(2));
+        assert_eq!(sprite.hr_version.unwrap().files.filename.unwrap(), "__test_mod__/graphics/hd/test/idle.png");
+    }
+
+    #[test]
+    fn sprite4WayWritesEachFrameAsADirection() {
+        let format = SpriteFormat {
+            name: String::from("base"),
+            direction_count: 4,
+            animation_length: 1,
+            target: OutputTarget::sprite4Way,
+            ..SpriteFormat::default()
+        };
+        let frame = |x: u32, width: i32, shift_x2: (i32, i32)| FrameRect { img_idx: 0, x, y: 0, width, height: 4, shift_x2 };
+        let sprites = FactorioSprites {
+            images: vec![RgbaImage::new(20, 4)],
+            slice: 1,
+            lines_per_file: 1,
+            stripes: None,
+            width: 6,
+            height: 4,
+            shift_x2: (0, 0),
+            debug_images: Vec::new(),
+            frames: Some(vec![frame(0, 4, (0, 0)), frame(4, 6, (2, 0)), frame(10, 4, (0, -4)), frame(14, 6, (-2, 0))]),
+        };
+        let category = String::from("test");
+        let config = config("sprite-4-way");
+        let prototype = writeResolutions(&category, &format, &sprites, &Preset::normal, &config).unwrap();
+        prototype.validate().unwrap();
+        let sprite = match prototype {
+            SheetPrototype::sprite4Way(sprite) => sprite,
+            _ => panic!("expected a Sprite4Way"),
+        };
+        let hd: Vec<(u32, i32, String)> = [&sprite.north, &sprite.east, &sprite.south, &sprite.west].iter()
+            .map(|direction| {
+                let hd = direction.hr_version.as_ref().unwrap();
+                (hd.x, hd.width, hd.shift[0].0.print(&lua::PRETTY))
+            })
+            .collect();
+        assert_eq!(hd, vec![
+            (0, 4, String::from("0")),
+            (4, 6, String::from("0.015625")),
+            (10, 4, String::from("0")),
+            (14, 6, String::from("-0.015625")),
+        ]);
+        assert_eq!(sprite.east.width, 3);
+        assert_eq!(sprite.south.hr_version.as_ref().unwrap().shift[1].0.print(&lua::PRETTY), "-0.03125");
+    }
+
+    #[test]
+    fn turretStatesBecomeLayersOfTheTurretSet() {
+        let format = |name: &str, state: TurretState| SpriteFormat {
+            name: String::from(name),
+            direction_count: 4,
+            animation_length: 2,
+            target: OutputTarget::turret(state),
+            ..SpriteFormat::default()
+        };
+        let (folded, attacking) = (format("folded", TurretState::folded), format("attack", TurretState::attacking));
+        let category = String::from("test");
+        let config = config("turret");
+        let outputs: Vec<FormatOutput> = [&folded, &attacking].iter()
+            .map(|format| {
+                let prototype = writeResolutions(&category, format, &gridSprites(4, 2), &Preset::normal, &config).unwrap();
+                prototype.validate().unwrap();
+                let animation = rotatedAnimation(prototype.clone());
+                assert_eq!(animation.files.frame_count, Some(2));
+                assert_eq!(animation.direction_count, 4);
+                FormatOutput {
+                    category: &category,
+                    format,
+                    preset: Preset::normal,
+                    sprites: prototype.toExp().unwrap(),
+                }
+            })
+            .collect();
+        let entries = turretEntries(&outputs);
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].0, "test_turret");
+        let states = match &entries[0].1 {
+            lua::Exp::Function { body, .. } => match &body.last_stat {
+                Some(lua::LastStat::Return { exp_list }) => match &exp_list[0] {
+                    lua::Exp::Table { field_list } => field_list.iter()
+                        .map(|(key, value)| (key.clone(), match value {
+                            lua::Exp::Table { field_list } => match &field_list[0].1 {
+                                lua::Exp::Array { member_list } => member_list.len(),
+                                _ => panic!("layers is not an array"),
+                            },
+                            _ => panic!("a state is not a table"),
+                        }))
+                        .collect::<Vec<_>>(),
+                    _ => panic!("the turret set is not a table"),
+                },
+                _ => panic!("no return"),
+            },
+            _ => panic!("not a function"),
+        };
+        assert_eq!(states, vec![(String::from("folded_animation"), 1), (String::from("attacking_animation"), 1)]);
+    }
+}
@@ -0,0 +1,74 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs::{File, create_dir_all};
+use std::io::Write;
+use std::path::Path;
+use image::RgbaImage;
+use image::imageops::overlay;
+use crate::casc::CascStorage;
+use crate::ddsgrp::DdsGrp;
+use crate::lua;
+use crate::lua::LuaSyntax;
+use crate::run_config::RunConfig;
+use crate::sheet_layout::gridLayout;
+use crate::sprite_config::GuiSpriteConfig;
+
+/// Puts every sprite on one sheet and writes `sprites.lua` with a `sprite` prototype for
+/// each. The sheet is a grid of equal cells with each sprite in the top left corner of
+/// its cell, so GUI styles can address them by position as well.
+pub fn writeGuiSprites(
+    storage: &mut CascStorage,
+    sprites: &[GuiSpriteConfig],
+    config: &RunConfig,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let mut grps: HashMap<&str, DdsGrp> = HashMap::new();
+    let mut pictures = Vec::with_capacity(sprites.len());
+    for sprite in sprites {
+        if !grps.contains_key(&sprite.source[..]) {
+            grps.insert(&sprite.source, DdsGrp::fromFile(storage.openFile(&sprite.source)?)?);
+        }
+        let grp = &grps[&sprite.source[..]];
+        let picture = grp.getFrame(sprite.frame).ok_or_else(|| format!(
+            "gui sprite {}: {} has {} frames, {} requested",
+            sprite.name, sprite.source, grp.frameCount(), sprite.frame,
+        ))?;
+        pictures.push(picture);
+    }
+    if pictures.is_empty() { return Ok(()); }
+
+    let cell_width = pictures.iter().map(|picture| picture.width()).max().unwrap();
+    let cell_height = pictures.iter().map(|picture| picture.height()).max().unwrap();
+    // a single file, so that every sprite is addressed by its position on the sheet
+    let frame_count = pictures.len() as i32;
+    let layout = gridLayout(cell_width as i32, cell_height as i32, frame_count, Some(frame_count), &config.hd_limits)
+        .map_err(|err| format!("gui sprites: {}", err))?;
+    let mut sheet = RgbaImage::new(layout.images[0].width as u32, layout.images[0].height as u32);
+
+    let sheet_path = config.sheetPath(&config.hd_folder, "gui", "sprites.png");
+    let mut prototypes = Vec::with_capacity(sprites.len());
+    for (i, (sprite, picture)) in sprites.iter().zip(pictures.iter()).enumerate() {
+        let (_, x, y) = layout.cellPosition(i as i32);
+        let (x, y) = (x as u32, y as u32);
+        overlay(&mut sheet, picture, x, y);
+        let integers = |values: [u32; 2]| lua::Exp::Array {
+            member_list: values.iter().map(|&value| lua::Exp::integer(value as i64)).collect(),
+        };
+        prototypes.push(lua::Exp::table(vec![
+            ("type", lua::Exp::string("sprite")),
+            ("name", lua::Exp::string(&sprite.name)),
+            ("filename", lua::Exp::String(format!("__{}__/{}", config.mod_name, sheet_path))),
+            ("position", integers([x, y])),
+            ("size", integers([picture.width(), picture.height()])),
+            ("flags", lua::Exp::Array { member_list: vec![lua::Exp::string("gui-icon")] }),
+        ]));
+    }
+
+    let path = Path::new(&config.output_dir).join(&sheet_path);
+    create_dir_all(path.parent().unwrap())?;
+    sheet.save(path)?;
+
+    let block = lua::wrap(Vec::new(), lua::Exp::Array { member_list: prototypes }, config.prototype_wrapper);
+    let mut file = File::create(format!("{}/sprites.lua", config.output_dir))?;
+    file.write_all(block.print(&config.lua_style).as_ref())?;
+    Ok(())
+}
@@ -0,0 +1,84 @@
+use std::error::Error;
+use std::fs::create_dir_all;
+use std::io::Cursor;
+use std::io::Read;
+use std::path::Path;
+use image::RgbaImage;
+use image::imageops::overlay;
+use crate::anim::Anim;
+use crate::casc::CascStorage;
+use crate::direction::{sourceFacing, STARCRAFT_ORIENTATION};
+use serde::Serialize;
+use crate::lua;
+use crate::lua_ser::toExp;
+use crate::run_config::{RunConfig, SdFilter, FactorioVersion};
+use crate::sprite_config::IconConfig;
+use crate::sprite_maker::{renderIcon, downscaleFrame};
+
+const ICON_SIZE: u32 = 64;
+/// 64, 32, 16 and 8 pixels, side by side.
+const ICON_MIPMAPS: u32 = 4;
+/// Facings of one animation step in the source anims.
+const SOURCE_DIRECTION_COUNT: i32 = 17;
+
+/// The icon fields of an item or entity prototype.
+#[derive(Serialize)]
+struct IconFields {
+    icon: String,
+    icon_size: u32,
+    icon_mipmaps: Option<u32>,
+}
+
+/// Centers `picture` on a square and scales it down to every mipmap level, placed next
+/// to each other from the largest.
+fn iconMipmaps(picture: &RgbaImage) -> RgbaImage {
+    let side = picture.width().max(picture.height());
+    let mut square = RgbaImage::new(side, side);
+    overlay(&mut square, picture, (side - picture.width()) / 2, (side - picture.height()) / 2);
+    let width = (0..ICON_MIPMAPS).map(|level| ICON_SIZE >> level).sum();
+    let mut output = RgbaImage::new(width, ICON_SIZE);
+    let mut x = 0;
+    for level in 0..ICON_MIPMAPS {
+        let size = ICON_SIZE >> level;
+        overlay(&mut output, &downscaleFrame(&square, size, size, SdFilter::lanczos3), x, 0);
+        x += size;
+    }
+    output
+}
+
+/// Writes an icon per config and returns the `icons` table of `anim.lua`, holding the
+/// icon fields of each by name.
+pub fn writeIcons(
+    storage: &mut CascStorage,
+    icons: &[IconConfig],
+    config: &RunConfig,
+) -> Result<lua::Exp, Box<dyn Error + Send + Sync>> {
+    let mut field_list = Vec::new();
+    for icon in icons {
+        let mut anim_data = Vec::new();
+        storage.openFile(&icon.source)?.read_to_end(&mut anim_data)?;
+        let anim = Anim::fromFile(Cursor::new(anim_data))?;
+        let facing = sourceFacing(icon.direction, SOURCE_DIRECTION_COUNT, &STARCRAFT_ORIENTATION);
+        let frame_idx = icon.first_frame + icon.frame * SOURCE_DIRECTION_COUNT + facing.source;
+        let picture = anim.getFrame(frame_idx as usize)?
+            .and_then(|frame| renderIcon(&frame, facing.mirrored, icon.tint))
+            .ok_or_else(|| format!("icon {}: frame {} has nothing to draw", icon.name, frame_idx))?;
+
+        let icon_path = config.sheetPath("icons", &icon.category, &format!("{}.png", icon.name));
+        let path = Path::new(&config.output_dir).join(&icon_path);
+        create_dir_all(path.parent().unwrap())?;
+        iconMipmaps(&picture).save(path)?;
+
+        let fields = IconFields {
+            icon: format!("__{}__/{}", config.mod_name, icon_path),
+            icon_size: ICON_SIZE,
+            // 2.0 counts the mipmaps from the image width
+            icon_mipmaps: match config.factorio_version {
+                FactorioVersion::v1_1 => Some(ICON_MIPMAPS),
+                FactorioVersion::v2_0 => None,
+            },
+        };
+        field_list.push((icon.name.clone(), toExp(&fields)?));
+    }
+    Ok(lua::Exp::Table { field_list })
+}
@@ -1,11 +1,118 @@
+use std::error::Error;
+use std::str::FromStr;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Layout {
+    /// A statement or table field per line, indented by two spaces.
+    pretty,
+    /// No whitespace that isn't needed, statements separated by `;`.
+    minified,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct PrintStyle {
+    pub layout: Layout,
+    /// Print table fields ordered by key rather than in insertion order.
+    pub sort_keys: bool,
+}
+
+pub const PRETTY: PrintStyle = PrintStyle { layout: Layout::pretty, sort_keys: false };
+
 pub trait LuaSyntax {
-    fn _prettyPrint(&self, indent: u32, buffer: &mut String);
+    fn _print(&self, style: &PrintStyle, indent: u32, buffer: &mut String);
 
-    fn prettyPrint(&self) -> String {
+    fn print(&self, style: &PrintStyle) -> String {
         let mut result = String::new();
-        self._prettyPrint(0, &mut result);
+        self._print(style, 0, &mut result);
         result
     }
+
+    fn prettyPrint(&self) -> String {
+        self.print(&PRETTY)
+    }
+}
+
+/// How a generated file hands its value to Factorio.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Wrapper {
+    /// `return exp`, for files loaded with `require`.
+    module,
+    /// `data:extend(exp)`, for prototype files.
+    dataExtend,
+}
+
+impl FromStr for Wrapper {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Wrapper, String> {
+        match name {
+            "module" => Ok(Wrapper::module),
+            "data-extend" => Ok(Wrapper::dataExtend),
+            _ => Err(format!("unknown wrapper: {}", name)),
+        }
+    }
+}
+
+/// A chunk running `stats` and then passing `exp` on as `wrapper` says.
+pub fn wrap(mut stats: Vec<Stat>, exp: Exp, wrapper: Wrapper) -> Block {
+    match wrapper {
+        Wrapper::module => Block {
+            stats,
+            last_stat: Some(LastStat::Return { exp_list: vec![exp] }),
+        },
+        Wrapper::dataExtend => {
+            stats.push(Stat::MethodCall {
+                object: Exp::Var(String::from("data")),
+                method: String::from("extend"),
+                par_list: vec![exp],
+            });
+            Block { stats, last_stat: None }
+        },
+    }
+}
+
+const RESERVED_WORDS: [&str; 22] = [
+    "and", "break", "do", "else", "elseif", "end", "false", "for", "function", "goto", "if",
+    "in", "local", "nil", "not", "or", "repeat", "return", "then", "true", "until", "while",
+];
+
+pub fn isReservedWord(name: &str) -> bool {
+    RESERVED_WORDS.contains(&name)
+}
+
+/// Whether `name` can be written as a bare name, e.g. as a table key without `[""]`.
+pub fn isIdentifier(name: &str) -> bool {
+    let mut chars = name.chars();
+    match chars.next() {
+        Some(c) if c.is_ascii_alphabetic() || c == '_' => {},
+        _ => return false,
+    }
+    chars.all(|c| c.is_ascii_alphanumeric() || c == '_') && !isReservedWord(name)
+}
+
+/// `value` as a double quoted Lua string literal.
+pub fn quoteString(value: &str) -> String {
+    let mut result = String::with_capacity(value.len() + 2);
+    result.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => result += "\\\"",
+            '\\' => result += "\\\\",
+            '\n' => result += "\\n",
+            '\r' => result += "\\r",
+            '\t' => result += "\\t",
+            // always three digits, so a digit after it isn't read as part of the escape
+            c if c.is_ascii_control() => result += &format!("\\{:03}", c as u32),
+            c => result.push(c),
+        }
+    }
+    result.push('"');
+    result
+}
+
+/// A table key as written in a constructor, bracketed when it isn't a name.
+fn tableKey(key: &str) -> String {
+    if isIdentifier(key) { String::from(key) } else { format!("[{}]", quoteString(key)) }
 }
 
 #[derive(Clone)]
@@ -16,150 +123,812 @@ pub struct Block {
 
 #[derive(Clone)]
 pub enum Stat {
-    Assignment{var_list: Vec<String>, exp_list: Vec<Exp>},
+    Assignment{var_list: Vec<Exp>, exp_list: Vec<Exp>},
+    Local{name_list: Vec<String>, exp_list: Vec<Exp>},
     FuncCall{function: Exp, par_list: Vec<Exp>},
+    MethodCall{object: Exp, method: String, par_list: Vec<Exp>},
+    Do{body: Block},
     While{condition: Exp, body: Block},
-    Repeat{condition: Exp, body: Block},
-    //If{condition: Exp, then_body: Block, elseif: Vec<Stat::ElseIf>, else_body: Block},
+    Repeat{body: Block, condition: Exp},
+    If{condition: Exp, then_body: Block, elseif_list: Vec<(Exp, Block)>, else_body: Option<Block>},
+    NumericFor{name: String, start: Exp, limit: Exp, step: Option<Exp>, body: Block},
+    GenericFor{name_list: Vec<String>, exp_list: Vec<Exp>, body: Block},
+    /// `function a.b:c() end`, the name including its dots and colon.
+    Function{name: String, par_list: Vec<String>, body: Block},
+    LocalFunction{name: String, par_list: Vec<String>, body: Block},
+    Goto{label: String},
+    Label{name: String},
+    Break,
 }
 
 #[derive(Clone)]
 pub enum LastStat {
     Return{exp_list: Vec<Exp>},
-    Break,
+}
+
+/// A finite double, the only kind Lua has a literal for. Made by `Exp::number`.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Finite(f64);
+
+impl Finite {
+    pub fn get(self) -> f64 {
+        self.0
+    }
 }
 
 #[derive(Clone)]
 pub enum Exp {
     Nil,
     Bool(bool),
-    Number(f32),
+    /// `...`
+    Vararg,
+    Integer(i64),
+    /// Made by `Exp::number`.
+    Number(Finite),
     String(String),
+    /// A name, see `Exp::var`.
     Var(String),
+    /// `prefix.name`
+    Field{prefix: Box<Exp>, name: String},
+    /// `prefix[key]`
+    Index{prefix: Box<Exp>, key: Box<Exp>},
     Function{par_list: Vec<String>, body: Block},
+    Call{function: Box<Exp>, arg_list: Vec<Exp>},
+    MethodCall{object: Box<Exp>, method: String, arg_list: Vec<Exp>},
     Array{member_list: Vec<Exp>},
     Table{field_list: Vec<(String, Exp)>},
+    /// A constructor with keys other than strings, e.g. `{[1] = a, [2] = b, n = 2}`.
+    KeyedTable{field_list: Vec<(Exp, Exp)>},
     Binop{left: Box<Exp>, op: String, right: Box<Exp>},
     Unop{op: String, right: Box<Exp>},
+    /// `(exp)`, which cuts the results of a call or `...` down to the first.
+    Paren(Box<Exp>),
 }
 
-impl LuaSyntax for Block {
-    fn _prettyPrint(&self, indent: u32, buffer: &mut String) {
+impl Exp {
+    /// Lua has no literal for NaN or the infinities, so they are an error here rather
+    /// than broken output.
+    pub fn number(value: f64) -> Result<Exp, Box<dyn Error + Send + Sync>> {
+        if value.is_finite() {
+            Ok(Exp::Number(Finite(value)))
+        } else {
+            Err(format!("{} can't be written as a lua number", value).into())
+        }
+    }
+
+    pub fn integer(value: i64) -> Exp {
+        Exp::Integer(value)
+    }
+
+    pub fn string(value: &str) -> Exp {
+        Exp::String(String::from(value))
+    }
+
+    /// A table of named fields, in the given order.
+    pub fn table(fields: Vec<(&str, Exp)>) -> Exp {
+        Exp::Table {
+            field_list: fields.into_iter().map(|(key, exp)| (String::from(key), exp)).collect()
+        }
+    }
+
+    /// A call of the function at the dotted `path`.
+    pub fn call(path: &str, arg_list: Vec<Exp>) -> Result<Exp, Box<dyn Error + Send + Sync>> {
+        Ok(Exp::Call { function: Box::new(Exp::path(path)?), arg_list })
+    }
+
+    pub fn var(name: &str) -> Result<Exp, Box<dyn Error + Send + Sync>> {
+        if isIdentifier(name) {
+            Ok(Exp::Var(String::from(name)))
+        } else {
+            Err(format!("{:?} is not a lua name", name).into())
+        }
+    }
+
+    /// A dotted path such as `anim.layered.carrier_run`, each part checked as a name.
+    pub fn path(path: &str) -> Result<Exp, Box<dyn Error + Send + Sync>> {
+        let mut parts = path.split('.');
+        let mut exp = Exp::var(parts.next().unwrap())?;
+        for part in parts {
+            exp = exp.field(part)?;
+        }
+        Ok(exp)
+    }
+
+    pub fn field(self, name: &str) -> Result<Exp, Box<dyn Error + Send + Sync>> {
+        if isIdentifier(name) {
+            Ok(Exp::Field { prefix: Box::new(self), name: String::from(name) })
+        } else {
+            Err(format!("{:?} is not a lua name", name).into())
+        }
+    }
+
+    /// Calls `visit` on this expression and then on every expression inside it.
+    pub fn walk(&self, visit: &mut dyn FnMut(&Exp)) {
+        visit(self);
+        match self {
+            Exp::Field{prefix, ..} => prefix.walk(visit),
+            Exp::Index{prefix, key} => {
+                prefix.walk(visit);
+                key.walk(visit);
+            },
+            Exp::Function{body, ..} => body.walk(visit),
+            Exp::Call{function, arg_list} => {
+                function.walk(visit);
+                arg_list.iter().for_each(|exp| exp.walk(visit));
+            },
+            Exp::MethodCall{object, arg_list, ..} => {
+                object.walk(visit);
+                arg_list.iter().for_each(|exp| exp.walk(visit));
+            },
+            Exp::Array{member_list} => member_list.iter().for_each(|exp| exp.walk(visit)),
+            Exp::Table{field_list} => field_list.iter().for_each(|(_, exp)| exp.walk(visit)),
+            Exp::KeyedTable{field_list} => field_list.iter().for_each(|(key, exp)| {
+                key.walk(visit);
+                exp.walk(visit);
+            }),
+            Exp::Binop{left, right, ..} => {
+                left.walk(visit);
+                right.walk(visit);
+            },
+            Exp::Unop{right, ..} | Exp::Paren(right) => right.walk(visit),
+            _ => {},
+        }
+    }
+
+    pub fn binop(left: Exp, op: &str, right: Exp) -> Result<Exp, Box<dyn Error + Send + Sync>> {
+        if binopPrecedence(op).is_none() {
+            return Err(format!("{:?} is not a lua binary operator", op).into());
+        }
+        Ok(Exp::Binop { left: Box::new(left), op: String::from(op), right: Box::new(right) })
+    }
+
+    /// Binding strength as an operand, higher binds tighter. Anything that isn't an
+    /// operation is atomic.
+    fn precedence(&self) -> u32 {
+        match self {
+            Exp::Binop{op, ..} => binopPrecedence(op).expect(UNKNOWN_OPERATOR),
+            Exp::Unop{..} => UNARY_PRECEDENCE,
+            Exp::Integer(num) if *num < 0 => UNARY_PRECEDENCE,
+            Exp::Number(num) if num.get().is_sign_negative() => UNARY_PRECEDENCE,
+            _ => ATOM_PRECEDENCE,
+        }
+    }
+
+    /// Whether the expression can be called or indexed without parentheses.
+    fn isPrefixExp(&self) -> bool {
+        matches!(self, Exp::Var(_) | Exp::Field{..} | Exp::Index{..} | Exp::Call{..} | Exp::MethodCall{..} | Exp::Paren(_))
+    }
+
+    fn _printPrefix(&self, style: &PrintStyle, indent: u32, buffer: &mut String) {
+        self._printOperand(!self.isPrefixExp(), style, indent, buffer);
+    }
+
+    fn _printOperand(&self, parenthesize: bool, style: &PrintStyle, indent: u32, buffer: &mut String) {
+        if parenthesize {*buffer += "(";}
+        self._print(style, indent, buffer);
+        if parenthesize {*buffer += ")";}
+    }
+}
+
+const UNARY_PRECEDENCE: u32 = 7;
+const POWER_PRECEDENCE: u32 = 8;
+const ATOM_PRECEDENCE: u32 = 9;
+
+const UNKNOWN_OPERATOR: &str = "binary operations are made by Exp::binop, which checks the operator";
+
+fn binopPrecedence(op: &str) -> Option<u32> {
+    Some(match op {
+        "or" => 1,
+        "and" => 2,
+        "<" | ">" | "<=" | ">=" | "~=" | "==" => 3,
+        ".." => 4,
+        "+" | "-" => 5,
+        "*" | "/" | "%" => 6,
+        "^" => POWER_PRECEDENCE,
+        _ => return None,
+    })
+}
+
+fn isRightAssociative(op: &str) -> bool {
+    op == ".." || op == "^"
+}
+
+/// A fraction kept exact until it is written, for values such as shifts that are whole
+/// pixels over a power of two, so they don't pick up float noise along the way.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Rational {
+    pub numerator: i64,
+    pub denominator: i64,
+}
+
+fn gcd(a: i64, b: i64) -> i64 {
+    if b == 0 { a.abs() } else { gcd(b, a % b) }
+}
+
+impl Rational {
+    pub fn new(numerator: i64, denominator: i64) -> Rational {
+        assert_ne!(denominator, 0, "rational with a zero denominator");
+        let divisor = gcd(numerator, denominator) * denominator.signum();
+        Rational { numerator: numerator / divisor, denominator: denominator / divisor }
+    }
+
+    /// `value` exactly, when it is a multiple of 2^-32 small enough to fit.
+    pub fn fromFloat(value: f64) -> Option<Rational> {
+        let denominator = 1i64 << 32;
+        let numerator = value * denominator as f64;
+        if numerator.fract() != 0.0 || numerator.abs() >= (1i64 << 52) as f64 {
+            return None;
+        }
+        Some(Rational::new(numerator as i64, denominator))
+    }
+
+    pub fn add(self, other: Rational) -> Rational {
+        Rational::new(
+            self.numerator * other.denominator + other.numerator * self.denominator,
+            self.denominator * other.denominator,
+        )
+    }
+
+    /// An integer when the fraction is whole, otherwise the closest double.
+    pub fn toExp(self) -> Exp {
+        if self.denominator == 1 {
+            Exp::Integer(self.numerator)
+        } else {
+            Exp::Number(Finite(self.numerator as f64 / self.denominator as f64))
+        }
+    }
+}
+
+impl Block {
+    /// Calls `visit` on every expression in the block, outer ones first.
+    pub fn walk(&self, visit: &mut dyn FnMut(&Exp)) {
         for stat in &self.stats {
-            for _ in 0..indent {*buffer += "  ";}
-            stat._prettyPrint(indent, buffer);
-            *buffer += "\n";
+            stat.walk(visit);
+        }
+        if let Some(LastStat::Return{exp_list}) = &self.last_stat {
+            exp_list.iter().for_each(|exp| exp.walk(visit));
         }
-        if let Some(stat) = &self.last_stat {
-            for _ in 0..indent {*buffer += "  ";}
-            stat._prettyPrint(indent, buffer);
+    }
+}
+
+impl Stat {
+    pub fn walk(&self, visit: &mut dyn FnMut(&Exp)) {
+        match self {
+            Stat::Assignment{var_list, exp_list} => {
+                var_list.iter().chain(exp_list).for_each(|exp| exp.walk(visit));
+            },
+            Stat::Local{exp_list, ..} => exp_list.iter().for_each(|exp| exp.walk(visit)),
+            Stat::FuncCall{function, par_list} => {
+                function.walk(visit);
+                par_list.iter().for_each(|exp| exp.walk(visit));
+            },
+            Stat::MethodCall{object, par_list, ..} => {
+                object.walk(visit);
+                par_list.iter().for_each(|exp| exp.walk(visit));
+            },
+            Stat::Do{body} => body.walk(visit),
+            Stat::While{condition, body} | Stat::Repeat{body, condition} => {
+                condition.walk(visit);
+                body.walk(visit);
+            },
+            Stat::If{condition, then_body, elseif_list, else_body} => {
+                condition.walk(visit);
+                then_body.walk(visit);
+                for (condition, body) in elseif_list {
+                    condition.walk(visit);
+                    body.walk(visit);
+                }
+                if let Some(body) = else_body { body.walk(visit); }
+            },
+            Stat::NumericFor{start, limit, step, body, ..} => {
+                start.walk(visit);
+                limit.walk(visit);
+                if let Some(step) = step { step.walk(visit); }
+                body.walk(visit);
+            },
+            Stat::GenericFor{exp_list, body, ..} => {
+                exp_list.iter().for_each(|exp| exp.walk(visit));
+                body.walk(visit);
+            },
+            Stat::Function{body, ..} | Stat::LocalFunction{body, ..} => body.walk(visit),
+            Stat::Goto{..} | Stat::Label{..} | Stat::Break => {},
+        }
+    }
+}
+
+fn printIndent(indent: u32, buffer: &mut String) {
+    for _ in 0..indent {*buffer += "  ";}
+}
+
+/// Moves to the next field or statement line at `indent`, or nowhere when minified.
+fn lineBreak(style: &PrintStyle, indent: u32, buffer: &mut String) {
+    if style.layout == Layout::pretty {
+        *buffer += "\n";
+        printIndent(indent, buffer);
+    }
+}
+
+/// The separator between list items.
+fn comma(style: &PrintStyle) -> &'static str {
+    match style.layout {
+        Layout::pretty => ", ",
+        Layout::minified => ",",
+    }
+}
+
+fn equals(style: &PrintStyle) -> &'static str {
+    match style.layout {
+        Layout::pretty => " = ",
+        Layout::minified => "=",
+    }
+}
+
+fn printList<T: LuaSyntax>(list: &[T], style: &PrintStyle, indent: u32, buffer: &mut String) {
+    for i in 0..list.len() {
+        list[i]._print(style, indent, buffer);
+        if i != list.len() - 1 {*buffer += comma(style)}
+    }
+}
+
+fn printNames(names: &[String], style: &PrintStyle, buffer: &mut String) {
+    *buffer += &names.join(comma(style));
+}
+
+/// `body` after its opening keyword, leaving the buffer where the closing keyword goes.
+fn printBlock(body: &Block, style: &PrintStyle, indent: u32, buffer: &mut String) {
+    match style.layout {
+        Layout::pretty => {
             *buffer += "\n";
+            body._print(style, indent + 1, buffer);
+            printIndent(indent, buffer);
+        },
+        Layout::minified => {
+            *buffer += " ";
+            let start = buffer.len();
+            body._print(style, indent + 1, buffer);
+            if buffer.len() != start {*buffer += " ";}
+        },
+    }
+}
+
+/// `{` and `}` around `count` fields, printed one by one by `printField`.
+fn printFields(
+    count: usize,
+    style: &PrintStyle,
+    indent: u32,
+    buffer: &mut String,
+    printField: &dyn Fn(usize, &mut String),
+) {
+    if count == 0 {
+        *buffer += "{}";
+        return;
+    }
+    *buffer += "{";
+    for i in 0..count {
+        lineBreak(style, indent + 1, buffer);
+        printField(i, buffer);
+        if i != count - 1 {*buffer += ","}
+    }
+    lineBreak(style, indent, buffer);
+    *buffer += "}";
+}
+
+impl LuaSyntax for Block {
+    fn _print(&self, style: &PrintStyle, indent: u32, buffer: &mut String) {
+        let last_stat = self.last_stat.iter().map(|stat| stat as &dyn LuaSyntax);
+        let stats = self.stats.iter().map(|stat| stat as &dyn LuaSyntax).chain(last_stat);
+        for (i, stat) in stats.enumerate() {
+            match style.layout {
+                Layout::pretty => {
+                    printIndent(indent, buffer);
+                    stat._print(style, indent, buffer);
+                    *buffer += "\n";
+                },
+                Layout::minified => {
+                    if i != 0 {*buffer += ";";}
+                    stat._print(style, indent, buffer);
+                },
+            }
         }
     }
 }
 
+impl LuaSyntax for String {
+    /// A name, as in parameter and variable lists.
+    fn _print(&self, _style: &PrintStyle, _indent: u32, buffer: &mut String) {
+        *buffer += self;
+    }
+}
+
 impl LuaSyntax for Stat {
-    fn _prettyPrint(&self, indent: u32, buffer: &mut String) {
+    fn _print(&self, style: &PrintStyle, indent: u32, buffer: &mut String) {
         match &self {
             Stat::Assignment{var_list, exp_list} => {
-                for i in 0..var_list.len() {
-                    *buffer += &*var_list[i];
-                    if i != var_list.len() - 1 {*buffer += ", "}
-                }
-                for i in 0..exp_list.len() {
-                    exp_list[i]._prettyPrint(indent, buffer);
-                    if i != exp_list.len() - 1 {*buffer += ", "}
-                }
+                printList(var_list, style, indent, buffer);
+                *buffer += equals(style);
+                printList(exp_list, style, indent, buffer);
+            },
+            Stat::Local{name_list, exp_list} => {
+                *buffer += "local ";
+                printNames(name_list, style, buffer);
+                if !exp_list.is_empty() {*buffer += equals(style);}
+                printList(exp_list, style, indent, buffer);
             },
             Stat::FuncCall{function, par_list} => {
-                function._prettyPrint(indent, buffer);
+                function._printPrefix(style, indent, buffer);
                 *buffer += "(";
-                for i in 0..par_list.len() {
-                    par_list[i]._prettyPrint(indent, buffer);
-                    if i != par_list.len() - 1 {*buffer += ", "}
-                }
+                printList(par_list, style, indent, buffer);
+                *buffer += ")";
+            },
+            Stat::MethodCall{object, method, par_list} => {
+                object._printPrefix(style, indent, buffer);
+                *buffer += &format!(":{}(", method);
+                printList(par_list, style, indent, buffer);
                 *buffer += ")";
             },
+            Stat::Do{body} => {
+                *buffer += "do";
+                printBlock(body, style, indent, buffer);
+                *buffer += "end";
+            },
             Stat::While{condition, body} => {
                 *buffer += "while ";
-                condition._prettyPrint(indent, buffer);
-                *buffer += " do\n";
-                body._prettyPrint(indent + 1, buffer);
-                for _ in 0..indent {*buffer += "  ";}
-                *buffer += "end\n";
+                condition._print(style, indent, buffer);
+                *buffer += " do";
+                printBlock(body, style, indent, buffer);
+                *buffer += "end";
+            },
+            Stat::Repeat{body, condition} => {
+                *buffer += "repeat";
+                printBlock(body, style, indent, buffer);
+                *buffer += "until ";
+                condition._print(style, indent, buffer);
+            },
+            Stat::If{condition, then_body, elseif_list, else_body} => {
+                *buffer += "if ";
+                condition._print(style, indent, buffer);
+                *buffer += " then";
+                printBlock(then_body, style, indent, buffer);
+                for (condition, body) in elseif_list {
+                    *buffer += "elseif ";
+                    condition._print(style, indent, buffer);
+                    *buffer += " then";
+                    printBlock(body, style, indent, buffer);
+                }
+                if let Some(body) = else_body {
+                    *buffer += "else";
+                    printBlock(body, style, indent, buffer);
+                }
+                *buffer += "end";
             },
-            _ => {}
+            Stat::NumericFor{name, start, limit, step, body} => {
+                *buffer += &format!("for {}{}", name, equals(style));
+                start._print(style, indent, buffer);
+                *buffer += comma(style);
+                limit._print(style, indent, buffer);
+                if let Some(step) = step {
+                    *buffer += comma(style);
+                    step._print(style, indent, buffer);
+                }
+                *buffer += " do";
+                printBlock(body, style, indent, buffer);
+                *buffer += "end";
+            },
+            Stat::GenericFor{name_list, exp_list, body} => {
+                *buffer += "for ";
+                printNames(name_list, style, buffer);
+                *buffer += " in ";
+                printList(exp_list, style, indent, buffer);
+                *buffer += " do";
+                printBlock(body, style, indent, buffer);
+                *buffer += "end";
+            },
+            Stat::Function{name, par_list, body} => {
+                *buffer += &format!("function {}(", name);
+                printNames(par_list, style, buffer);
+                *buffer += ")";
+                printBlock(body, style, indent, buffer);
+                *buffer += "end";
+            },
+            Stat::LocalFunction{name, par_list, body} => {
+                *buffer += &format!("local function {}(", name);
+                printNames(par_list, style, buffer);
+                *buffer += ")";
+                printBlock(body, style, indent, buffer);
+                *buffer += "end";
+            },
+            Stat::Goto{label} => *buffer += &format!("goto {}", label),
+            Stat::Label{name} => *buffer += &format!("::{}::", name),
+            Stat::Break => *buffer += "break",
         }
     }
 }
 
 impl LuaSyntax for LastStat {
-    fn _prettyPrint(&self, indent: u32, buffer: &mut String) {
+    fn _print(&self, style: &PrintStyle, indent: u32, buffer: &mut String) {
         match &self {
             LastStat::Return{exp_list} => {
-                *buffer += "return ";
-                for i in 0..exp_list.len() {
-                    exp_list[i]._prettyPrint(indent, buffer);
-                    if i != exp_list.len() - 1 {*buffer += ", "}
-                }
+                *buffer += "return";
+                if !exp_list.is_empty() {*buffer += " ";}
+                printList(exp_list, style, indent, buffer);
             },
-            LastStat::Break => *buffer += "break",
         }
     }
 }
 
+/// Order of keyed table fields when sorting: integer keys by value, then the rest by how
+/// they are written.
+fn keyOrder(key: &Exp) -> (bool, i64, String) {
+    match key {
+        Exp::Integer(num) => (false, *num, String::new()),
+        Exp::String(key) => (true, 0, tableKey(key)),
+        key => (true, 0, format!("[{}]", key.print(&PrintStyle { layout: Layout::minified, sort_keys: true }))),
+    }
+}
+
 impl LuaSyntax for Exp {
-    fn _prettyPrint(&self, indent: u32, buffer: &mut String) {
+    fn _print(&self, style: &PrintStyle, indent: u32, buffer: &mut String) {
         match &self {
             Exp::Nil => *buffer += "nil",
+            Exp::Vararg => *buffer += "...",
             Exp::Bool(val) => *buffer += if *val {"true"} else {"false"},
-            Exp::Number(num) => *buffer += &*num.to_string(),
-            Exp::String(str) => *buffer += &format!("\"{}\"", str),
+            Exp::Integer(num) => *buffer += &*num.to_string(),
+            Exp::Number(num) => {
+                // the shortest form that reads back as the same double
+                *buffer += &format!("{:?}", num.get());
+            },
+            Exp::String(str) => *buffer += &quoteString(str),
             Exp::Var(var_name) => *buffer += var_name,
+            Exp::Field{prefix, name} => {
+                prefix._printPrefix(style, indent, buffer);
+                *buffer += ".";
+                *buffer += name;
+            },
+            Exp::Index{prefix, key} => {
+                prefix._printPrefix(style, indent, buffer);
+                *buffer += "[";
+                key._print(style, indent, buffer);
+                *buffer += "]";
+            },
             Exp::Function{par_list, body} => {
                 *buffer += "function(";
-                for i in 0..par_list.len() {
-                    *buffer += &par_list[i];
-                    if i != par_list.len() - 1 {*buffer += ", ";}
-                }
-                *buffer += ")\n";
-                body._prettyPrint(indent + 1, buffer);
-                for _ in 0..indent {*buffer += "  ";}
+                printNames(par_list, style, buffer);
+                *buffer += ")";
+                printBlock(body, style, indent, buffer);
                 *buffer += "end";
             },
+            Exp::Call{function, arg_list} => {
+                function._printPrefix(style, indent, buffer);
+                *buffer += "(";
+                printList(arg_list, style, indent, buffer);
+                *buffer += ")";
+            },
+            Exp::MethodCall{object, method, arg_list} => {
+                object._printPrefix(style, indent, buffer);
+                *buffer += &format!(":{}(", method);
+                printList(arg_list, style, indent, buffer);
+                *buffer += ")";
+            },
             Exp::Array{member_list} => {
-                *buffer += "{\n";
-                for i in 0..member_list.len() {
-                    for _ in 0..(indent + 1) {*buffer += "  ";}
-                    member_list[i]._prettyPrint(indent + 1, buffer);
-                    if i != member_list.len() - 1 {*buffer += ","}
-                    *buffer += "\n";
-                }
-                for _ in 0..indent {*buffer += "  ";}
-                *buffer += "}";
+                printFields(member_list.len(), style, indent, buffer, &|i, buffer| {
+                    member_list[i]._print(style, indent + 1, buffer);
+                });
             },
             Exp::Table{field_list} => {
-                *buffer += "{\n";
-                for i in 0..field_list.len() {
-                    for _ in 0..(indent + 1) {*buffer += "  ";}
-                    *buffer += &field_list[i].0;
-                    *buffer += " = ";
-                    field_list[i].1._prettyPrint(indent + 1, buffer);
-                    if i != field_list.len() - 1 {*buffer += ","}
-                    *buffer += "\n";
+                let mut sorted: Vec<&(String, Exp)> = field_list.iter().collect();
+                if style.sort_keys {
+                    sorted.sort_by(|a, b| a.0.cmp(&b.0));
+                }
+                printFields(sorted.len(), style, indent, buffer, &|i, buffer| {
+                    *buffer += &tableKey(&sorted[i].0);
+                    *buffer += equals(style);
+                    sorted[i].1._print(style, indent + 1, buffer);
+                });
+            },
+            Exp::KeyedTable{field_list} => {
+                let mut sorted: Vec<&(Exp, Exp)> = field_list.iter().collect();
+                if style.sort_keys {
+                    sorted.sort_by_key(|(key, _)| keyOrder(key));
                 }
-                for _ in 0..indent {*buffer += "  ";}
-                *buffer += "}";
+                printFields(sorted.len(), style, indent, buffer, &|i, buffer| {
+                    match &sorted[i].0 {
+                        Exp::String(key) => *buffer += &tableKey(key),
+                        key => {
+                            *buffer += "[";
+                            key._print(style, indent + 1, buffer);
+                            *buffer += "]";
+                        },
+                    }
+                    *buffer += equals(style);
+                    sorted[i].1._print(style, indent + 1, buffer);
+                });
             },
             Exp::Binop{left, op, right} => {
-                left._prettyPrint(indent, buffer);
-                *buffer += &format!(" {} ", op);
-                right._prettyPrint(indent, buffer);
+                let precedence = binopPrecedence(op).expect(UNKNOWN_OPERATOR);
+                let right_associative = isRightAssociative(op);
+                let left_precedence = left.precedence();
+                left._printOperand(
+                    // also covers -x ^ y being -(x ^ y)
+                    left_precedence < precedence || (left_precedence == precedence && right_associative),
+                    style,
+                    indent,
+                    buffer,
+                );
+                let right_precedence = right.precedence();
+                let mut operand = String::new();
+                right._printOperand(
+                    right_precedence < precedence
+                        || (right_precedence == precedence && !right_associative),
+                    style,
+                    indent,
+                    &mut operand,
+                );
+                // words need the spaces, `1..2` reads as a malformed number and `a--b` as a comment
+                let spaced = style.layout == Layout::pretty
+                    || op.ends_with(|c: char| c.is_ascii_alphabetic())
+                    || op == ".."
+                    || (op.ends_with('-') && operand.starts_with('-'));
+                if spaced {
+                    *buffer += &format!(" {} ", op);
+                } else {
+                    *buffer += op;
+                }
+                *buffer += &operand;
             },
             Exp::Unop{op, right} => {
-                *buffer += &op;
-                right._prettyPrint(indent, buffer);
-            }
+                *buffer += op;
+                let parenthesize = right.precedence() < UNARY_PRECEDENCE;
+                let mut operand = String::new();
+                right._printOperand(parenthesize, style, indent, &mut operand);
+                // `not x`, and `- -x` rather than a `--` comment
+                if op.ends_with(|c: char| c.is_ascii_alphabetic()) || (op.ends_with('-') && operand.starts_with('-')) {
+                    *buffer += " ";
+                }
+                *buffer += &operand;
+            },
+            Exp::Paren(exp) => exp._printOperand(true, style, indent, buffer),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MINIFIED: PrintStyle = PrintStyle { layout: Layout::minified, sort_keys: false };
+
+    fn num(value: f64) -> Exp {
+        Exp::number(value).unwrap()
+    }
+
+    fn var(name: &str) -> Exp {
+        Exp::var(name).unwrap()
+    }
+
+    fn bin(left: Exp, op: &str, right: Exp) -> Exp {
+        Exp::binop(left, op, right).unwrap()
+    }
+
+    fn unop(op: &str, right: Exp) -> Exp {
+        Exp::Unop { op: String::from(op), right: Box::new(right) }
+    }
+
+    #[test]
+    fn numbersReadBackExactly() {
+        for &value in &[0.5, -0.5, 0.1, 1.0 / 3.0, 1e300, 5e-324] {
+            assert_eq!(num(value).prettyPrint().parse::<f64>().unwrap(), value);
+        }
+        assert_eq!(num(2.0).prettyPrint(), "2.0");
+    }
+
+    #[test]
+    fn rejectsNonFiniteNumbers() {
+        for &value in &[f64::NAN, f64::INFINITY, f64::NEG_INFINITY] {
+            assert!(Exp::number(value).is_err(), "{}", value);
+        }
+    }
+
+    #[test]
+    fn subtractsANegativeNumber() {
+        let exp = bin(Exp::Integer(1), "-", Exp::Integer(-2));
+        assert_eq!(exp.prettyPrint(), "1 - -2");
+        assert_eq!(exp.print(&MINIFIED), "1 - -2");
+        let exp = bin(Exp::Integer(1), "-", unop("-", var("x")));
+        assert_eq!(exp.print(&MINIFIED), "1 - -x");
+    }
+
+    #[test]
+    fn parenthesizesANegativeBase() {
+        assert_eq!(bin(num(-0.5), "^", Exp::Integer(2)).prettyPrint(), "(-0.5) ^ 2");
+        assert_eq!(bin(unop("-", var("x")), "^", Exp::Integer(2)).prettyPrint(), "(-x) ^ 2");
+        assert_eq!(unop("-", bin(var("x"), "^", Exp::Integer(2))).prettyPrint(), "-x ^ 2");
+    }
+
+    #[test]
+    fn parenthesizesANegativeExponent() {
+        assert_eq!(bin(Exp::Integer(2), "^", unop("-", var("x"))).prettyPrint(), "2 ^ (-x)");
+        assert_eq!(bin(Exp::Integer(2), "^", num(-0.5)).print(&MINIFIED), "2^(-0.5)");
+    }
+
+    #[test]
+    fn keepsAssociativity() {
+        let left = bin(bin(var("a"), "-", var("b")), "-", var("c"));
+        assert_eq!(left.prettyPrint(), "a - b - c");
+        let right = bin(var("a"), "-", bin(var("b"), "-", var("c")));
+        assert_eq!(right.prettyPrint(), "a - (b - c)");
+        let power = bin(var("a"), "^", bin(var("b"), "^", var("c")));
+        assert_eq!(power.prettyPrint(), "a ^ b ^ c");
+        let power = bin(bin(var("a"), "^", var("b")), "^", var("c"));
+        assert_eq!(power.prettyPrint(), "(a ^ b) ^ c");
+    }
+
+    #[test]
+    fn escapesStrings() {
+        assert_eq!(quoteString(r#"say "hi" \o/"#), r#""say \"hi\" \\o/""#);
+        assert_eq!(quoteString("a\nb\rc\td"), r#""a\nb\rc\td""#);
+        // a digit after a short escape would be read as part of it
+        assert_eq!(quoteString("\u{1}9\0\u{7f}"), r#""\0019\000\127""#);
+        assert_eq!(quoteString("é"), "\"é\"");
+    }
+
+    #[test]
+    fn bracketsKeysThatAreNotNames() {
+        let table = Exp::Table {
+            field_list: vec![
+                (String::from("name"), Exp::Integer(1)),
+                (String::from("end"), Exp::Integer(2)),
+                (String::from("a-b"), Exp::Integer(3)),
+                (String::from("1st"), Exp::Integer(4)),
+                (String::from(""), Exp::Integer(5)),
+            ],
+        };
+        assert_eq!(table.print(&MINIFIED), r#"{name=1,["end"]=2,["a-b"]=3,["1st"]=4,[""]=5}"#);
+    }
+
+    #[test]
+    fn rejectsReservedWordsAsNames() {
+        assert!(Exp::var("end").is_err());
+        assert!(Exp::var("a.b").is_err());
+        assert!(Exp::path("anim.then").is_err());
+        assert!(Exp::var("_end").is_ok());
+        assert!(Exp::path("anim.layered.carrier_run").is_ok());
+    }
+
+    #[test]
+    fn minifiedSpacing() {
+        assert_eq!(bin(var("a"), "..", var("b")).print(&MINIFIED), "a .. b");
+        assert_eq!(bin(Exp::Integer(1), "..", Exp::Integer(2)).print(&MINIFIED), "1 .. 2");
+        assert_eq!(bin(var("a"), "and", unop("not", var("b"))).print(&MINIFIED), "a and not b");
+        assert_eq!(unop("-", unop("-", var("x"))).print(&MINIFIED), "- -x");
+        assert_eq!(bin(var("a"), "*", var("b")).print(&MINIFIED), "a*b");
+        let block = wrap(
+            vec![Stat::Local { name_list: vec![String::from("a"), String::from("b")], exp_list: vec![Exp::Integer(1), Exp::Integer(2)] }],
+            Exp::Array { member_list: vec![var("a"), var("b")] },
+            Wrapper::module,
+        );
+        assert_eq!(block.print(&MINIFIED), "local a,b=1,2;return {a,b}");
+    }
+
+    #[test]
+    fn emptyTables() {
+        let table = Exp::Table { field_list: vec![(String::from("flags"), Exp::Array { member_list: Vec::new() })] };
+        assert_eq!(table.prettyPrint(), "{\n  flags = {}\n}");
+        assert_eq!(Exp::Table { field_list: Vec::new() }.prettyPrint(), "{}");
+        assert_eq!(Exp::KeyedTable { field_list: Vec::new() }.print(&MINIFIED), "{}");
+    }
+
+    #[test]
+    fn wrappers() {
+        let exp = || Exp::Array { member_list: vec![Exp::Integer(1)] };
+        assert_eq!(wrap(Vec::new(), exp(), "module".parse().unwrap()).print(&MINIFIED), "return {1}");
+        assert_eq!(wrap(Vec::new(), exp(), "data-extend".parse().unwrap()).print(&MINIFIED), "data:extend({1})");
+        assert!("extend".parse::<Wrapper>().is_err());
+    }
+
+    #[test]
+    fn unknownOperatorsAreRejected() {
+        assert!(Exp::binop(var("a"), "!=", var("b")).is_err());
+        assert!(Exp::binop(var("a"), "//", var("b")).is_err());
+        assert!(Exp::binop(var("a"), "~=", var("b")).is_ok());
+    }
+}
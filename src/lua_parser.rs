@@ -0,0 +1,872 @@
+use std::error::Error;
+use crate::lua::{Block, Stat, LastStat, Exp, isReservedWord};
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Name(String),
+    Keyword(String),
+    String(String),
+    Integer(i64),
+    Number(f64),
+    Symbol(&'static str),
+    Eof,
+}
+
+// longest first, so `...` isn't read as `..` and `.`
+const SYMBOLS: [&str; 27] = [
+    "...", "..", "==", "~=", "<=", ">=", "::",
+    "+", "-", "*", "/", "%", "^", "#", "<", ">", "=", "(", ")", "{", "}", "[", "]", ";", ":", ",",
+    ".",
+];
+
+struct Lexer<'a> {
+    source: &'a [u8],
+    pos: usize,
+    line: u32,
+}
+
+type ParseResult<T> = Result<T, Box<dyn Error + Send + Sync>>;
+
+impl Lexer<'_> {
+    fn error<T>(&self, message: &str) -> ParseResult<T> {
+        Err(format!("line {}: {}", self.line, message).into())
+    }
+
+    fn peekByte(&self, offset: usize) -> u8 {
+        *self.source.get(self.pos + offset).unwrap_or(&0)
+    }
+
+    fn advance(&mut self) -> u8 {
+        let c = self.peekByte(0);
+        if c == b'\n' { self.line += 1; }
+        self.pos += 1;
+        c
+    }
+
+    /// Level of a `[[` or `[==[` opening at the current position.
+    fn longBracketLevel(&self) -> Option<usize> {
+        if self.peekByte(0) != b'[' { return None; }
+        let level = (1..).take_while(|i| self.peekByte(*i) == b'=').count();
+        if self.peekByte(level + 1) == b'[' { Some(level) } else { None }
+    }
+
+    fn longBracket(&mut self, level: usize) -> ParseResult<String> {
+        self.pos += level + 2;
+        // a newline right after the opening bracket is skipped
+        if self.peekByte(0) == b'\r' { self.advance(); }
+        if self.peekByte(0) == b'\n' { self.advance(); }
+        let start = self.pos;
+        loop {
+            if self.pos >= self.source.len() {
+                return self.error("unfinished long string or comment");
+            }
+            if self.peekByte(0) == b']'
+                && (1..=level).all(|i| self.peekByte(i) == b'=')
+                && self.peekByte(level + 1) == b']' {
+                let content = String::from_utf8_lossy(&self.source[start..self.pos]).into_owned();
+                self.pos += level + 2;
+                return Ok(content);
+            }
+            self.advance();
+        }
+    }
+
+    fn skipWhitespaceAndComments(&mut self) -> ParseResult<()> {
+        loop {
+            match self.peekByte(0) {
+                b' ' | b'\t' | b'\r' | b'\n' | 0x0b | 0x0c => { self.advance(); },
+                b'-' if self.peekByte(1) == b'-' => {
+                    self.pos += 2;
+                    match self.longBracketLevel() {
+                        Some(level) => { self.longBracket(level)?; },
+                        None => while self.pos < self.source.len() && self.peekByte(0) != b'\n' {
+                            self.advance();
+                        },
+                    }
+                },
+                _ => return Ok(()),
+            }
+        }
+    }
+
+    fn quotedString(&mut self) -> ParseResult<String> {
+        let quote = self.advance();
+        let mut bytes = Vec::new();
+        loop {
+            let c = self.advance();
+            match c {
+                0 if self.pos > self.source.len() => return self.error("unfinished string"),
+                b'\n' => return self.error("unfinished string"),
+                c if c == quote => break,
+                b'\\' => {
+                    let escape = self.advance();
+                    match escape {
+                        b'n' => bytes.push(b'\n'),
+                        b't' => bytes.push(b'\t'),
+                        b'r' => bytes.push(b'\r'),
+                        b'a' => bytes.push(0x07),
+                        b'b' => bytes.push(0x08),
+                        b'f' => bytes.push(0x0c),
+                        b'v' => bytes.push(0x0b),
+                        b'\\' | b'"' | b'\'' | b'\n' => bytes.push(escape),
+                        b'x' => {
+                            let digits = [self.advance(), self.advance()];
+                            let value = std::str::from_utf8(&digits).ok()
+                                .and_then(|digits| u8::from_str_radix(digits, 16).ok());
+                            match value {
+                                Some(value) => bytes.push(value),
+                                None => return self.error("invalid \\x escape"),
+                            }
+                        },
+                        b'z' => while self.peekByte(0).is_ascii_whitespace() { self.advance(); },
+                        b'0'..=b'9' => {
+                            let mut value = (escape - b'0') as u32;
+                            for _ in 0..2 {
+                                if !self.peekByte(0).is_ascii_digit() { break; }
+                                value = value * 10 + (self.advance() - b'0') as u32;
+                            }
+                            if value > 255 { return self.error("decimal escape too large"); }
+                            bytes.push(value as u8);
+                        },
+                        _ => return self.error("invalid escape sequence"),
+                    }
+                },
+                c => bytes.push(c),
+            }
+        }
+        Ok(String::from_utf8_lossy(&bytes).into_owned())
+    }
+
+    fn number(&mut self) -> ParseResult<Token> {
+        let start = self.pos;
+        let hex = self.peekByte(0) == b'0' && (self.peekByte(1) | 0x20) == b'x';
+        if hex { self.pos += 2; }
+        let exponent = if hex { b'p' } else { b'e' };
+        let mut is_float = false;
+        loop {
+            let c = self.peekByte(0);
+            if (c | 0x20) == exponent {
+                is_float = true;
+                self.pos += 1;
+                if self.peekByte(0) == b'+' || self.peekByte(0) == b'-' { self.pos += 1; }
+            } else if c == b'.' {
+                is_float = true;
+                self.pos += 1;
+            } else if c.is_ascii_hexdigit() && (hex || c.is_ascii_digit()) {
+                self.pos += 1;
+            } else {
+                break;
+            }
+        }
+        let text = std::str::from_utf8(&self.source[start..self.pos]).unwrap();
+        if hex {
+            if is_float { return self.error("hexadecimal floats are not supported"); }
+            // wraps around like Lua does
+            return match u64::from_str_radix(&text[2..], 16) {
+                Ok(value) => Ok(Token::Integer(value as i64)),
+                Err(_) => self.error(&format!("malformed number {}", text)),
+            };
+        }
+        if !is_float {
+            if let Ok(value) = text.parse::<i64>() {
+                return Ok(Token::Integer(value));
+            }
+        }
+        match text.parse::<f64>() {
+            Ok(value) => Ok(Token::Number(value)),
+            Err(_) => self.error(&format!("malformed number {}", text)),
+        }
+    }
+
+    fn next(&mut self) -> ParseResult<Token> {
+        self.skipWhitespaceAndComments()?;
+        let c = self.peekByte(0);
+        if self.pos >= self.source.len() {
+            return Ok(Token::Eof);
+        }
+        if c.is_ascii_alphabetic() || c == b'_' {
+            let start = self.pos;
+            while self.peekByte(0).is_ascii_alphanumeric() || self.peekByte(0) == b'_' { self.pos += 1; }
+            let name = String::from_utf8_lossy(&self.source[start..self.pos]).into_owned();
+            return Ok(if isReservedWord(&name) { Token::Keyword(name) } else { Token::Name(name) });
+        }
+        if c.is_ascii_digit() || (c == b'.' && self.peekByte(1).is_ascii_digit()) {
+            return self.number();
+        }
+        if c == b'"' || c == b'\'' {
+            return Ok(Token::String(self.quotedString()?));
+        }
+        if let Some(level) = self.longBracketLevel() {
+            return Ok(Token::String(self.longBracket(level)?));
+        }
+        for symbol in SYMBOLS.iter() {
+            if self.source[self.pos..].starts_with(symbol.as_bytes()) {
+                self.pos += symbol.len();
+                return Ok(Token::Symbol(symbol));
+            }
+        }
+        self.error(&format!("unexpected character {:?}", c as char))
+    }
+}
+
+struct Parser<'a> {
+    lexer: Lexer<'a>,
+    token: Token,
+    /// Line of `token`.
+    line: u32,
+    lookahead: Option<(Token, u32)>,
+}
+
+/// Left and right priority of each binary operator, as in the reference implementation.
+fn binaryPriority(token: &Token) -> Option<(&'static str, u32, u32)> {
+    let op = match token {
+        Token::Keyword(word) if word == "or" => "or",
+        Token::Keyword(word) if word == "and" => "and",
+        Token::Symbol(symbol) => symbol,
+        _ => return None,
+    };
+    let (left, right) = match op {
+        "or" => (1, 1),
+        "and" => (2, 2),
+        "<" | ">" | "<=" | ">=" | "~=" | "==" => (3, 3),
+        ".." => (9, 8),
+        "+" | "-" => (10, 10),
+        "*" | "/" | "%" => (11, 11),
+        "^" => (14, 13),
+        _ => return None,
+    };
+    Some((op, left, right))
+}
+
+const UNARY_PRIORITY: u32 = 12;
+
+impl Parser<'_> {
+    fn error<T>(&self, message: &str) -> ParseResult<T> {
+        Err(format!("line {}: {}", self.line, message).into())
+    }
+
+    fn advance(&mut self) -> ParseResult<Token> {
+        let (next, line) = match self.lookahead.take() {
+            Some(lookahead) => lookahead,
+            None => {
+                let token = self.lexer.next()?;
+                (token, self.lexer.line)
+            },
+        };
+        self.line = line;
+        Ok(std::mem::replace(&mut self.token, next))
+    }
+
+    fn peek(&mut self) -> ParseResult<&Token> {
+        if self.lookahead.is_none() {
+            let token = self.lexer.next()?;
+            self.lookahead = Some((token, self.lexer.line));
+        }
+        Ok(&self.lookahead.as_ref().unwrap().0)
+    }
+
+    fn isSymbol(&self, symbol: &str) -> bool {
+        matches!(&self.token, Token::Symbol(s) if *s == symbol)
+    }
+
+    fn isKeyword(&self, keyword: &str) -> bool {
+        match &self.token {
+            Token::Keyword(word) => word == keyword,
+            _ => false,
+        }
+    }
+
+    fn testSymbol(&mut self, symbol: &str) -> ParseResult<bool> {
+        if self.isSymbol(symbol) {
+            self.advance()?;
+            Ok(true)
+        } else {
+            Ok(false)
+        }
+    }
+
+    fn expectSymbol(&mut self, symbol: &str) -> ParseResult<()> {
+        if self.testSymbol(symbol)? { Ok(()) } else { self.error(&format!("'{}' expected, found {:?}", symbol, self.token)) }
+    }
+
+    fn expectKeyword(&mut self, keyword: &str) -> ParseResult<()> {
+        if self.isKeyword(keyword) {
+            self.advance()?;
+            Ok(())
+        } else {
+            self.error(&format!("'{}' expected, found {:?}", keyword, self.token))
+        }
+    }
+
+    fn name(&mut self) -> ParseResult<String> {
+        match self.advance()? {
+            Token::Name(name) => Ok(name),
+            token => self.error(&format!("name expected, found {:?}", token)),
+        }
+    }
+
+    fn blockFollows(&self) -> bool {
+        match &self.token {
+            Token::Eof => true,
+            Token::Keyword(word) => ["else", "elseif", "end", "until"].contains(&&word[..]),
+            _ => false,
+        }
+    }
+
+    fn block(&mut self) -> ParseResult<Block> {
+        let mut stats = Vec::new();
+        let mut last_stat = None;
+        while !self.blockFollows() {
+            if self.testSymbol(";")? { continue; }
+            if self.isKeyword("return") {
+                self.advance()?;
+                let exp_list = if self.blockFollows() || self.isSymbol(";") {
+                    Vec::new()
+                } else {
+                    self.expList()?
+                };
+                self.testSymbol(";")?;
+                last_stat = Some(LastStat::Return { exp_list });
+                break;
+            }
+            stats.push(self.statement()?);
+        }
+        Ok(Block { stats, last_stat })
+    }
+
+    fn funcBody(&mut self) -> ParseResult<(Vec<String>, Block)> {
+        self.expectSymbol("(")?;
+        let mut par_list = Vec::new();
+        if !self.isSymbol(")") {
+            loop {
+                if self.testSymbol("...")? {
+                    par_list.push(String::from("..."));
+                    break;
+                }
+                par_list.push(self.name()?);
+                if !self.testSymbol(",")? { break; }
+            }
+        }
+        self.expectSymbol(")")?;
+        let body = self.block()?;
+        self.expectKeyword("end")?;
+        Ok((par_list, body))
+    }
+
+    fn statement(&mut self) -> ParseResult<Stat> {
+        let keyword = match &self.token {
+            Token::Keyword(word) => Some(word.clone()),
+            _ => None,
+        };
+        match keyword.as_deref() {
+            Some("if") => {
+                self.advance()?;
+                let condition = self.exp()?;
+                self.expectKeyword("then")?;
+                let then_body = self.block()?;
+                let mut elseif_list = Vec::new();
+                while self.isKeyword("elseif") {
+                    self.advance()?;
+                    let condition = self.exp()?;
+                    self.expectKeyword("then")?;
+                    elseif_list.push((condition, self.block()?));
+                }
+                let else_body = if self.isKeyword("else") {
+                    self.advance()?;
+                    Some(self.block()?)
+                } else {
+                    None
+                };
+                self.expectKeyword("end")?;
+                Ok(Stat::If { condition, then_body, elseif_list, else_body })
+            },
+            Some("while") => {
+                self.advance()?;
+                let condition = self.exp()?;
+                self.expectKeyword("do")?;
+                let body = self.block()?;
+                self.expectKeyword("end")?;
+                Ok(Stat::While { condition, body })
+            },
+            Some("do") => {
+                self.advance()?;
+                let body = self.block()?;
+                self.expectKeyword("end")?;
+                Ok(Stat::Do { body })
+            },
+            Some("for") => {
+                self.advance()?;
+                let name = self.name()?;
+                if self.testSymbol("=")? {
+                    let start = self.exp()?;
+                    self.expectSymbol(",")?;
+                    let limit = self.exp()?;
+                    let step = if self.testSymbol(",")? { Some(self.exp()?) } else { None };
+                    self.expectKeyword("do")?;
+                    let body = self.block()?;
+                    self.expectKeyword("end")?;
+                    Ok(Stat::NumericFor { name, start, limit, step, body })
+                } else {
+                    let mut name_list = vec![name];
+                    while self.testSymbol(",")? {
+                        name_list.push(self.name()?);
+                    }
+                    self.expectKeyword("in")?;
+                    let exp_list = self.expList()?;
+                    self.expectKeyword("do")?;
+                    let body = self.block()?;
+                    self.expectKeyword("end")?;
+                    Ok(Stat::GenericFor { name_list, exp_list, body })
+                }
+            },
+            Some("repeat") => {
+                self.advance()?;
+                let body = self.block()?;
+                self.expectKeyword("until")?;
+                let condition = self.exp()?;
+                Ok(Stat::Repeat { body, condition })
+            },
+            Some("function") => {
+                self.advance()?;
+                let mut name = self.name()?;
+                while self.testSymbol(".")? {
+                    name = format!("{}.{}", name, self.name()?);
+                }
+                if self.testSymbol(":")? {
+                    name = format!("{}:{}", name, self.name()?);
+                }
+                let (par_list, body) = self.funcBody()?;
+                Ok(Stat::Function { name, par_list, body })
+            },
+            Some("local") => {
+                self.advance()?;
+                if self.isKeyword("function") {
+                    self.advance()?;
+                    let name = self.name()?;
+                    let (par_list, body) = self.funcBody()?;
+                    return Ok(Stat::LocalFunction { name, par_list, body });
+                }
+                let mut name_list = vec![self.name()?];
+                while self.testSymbol(",")? {
+                    name_list.push(self.name()?);
+                }
+                let exp_list = if self.testSymbol("=")? { self.expList()? } else { Vec::new() };
+                Ok(Stat::Local { name_list, exp_list })
+            },
+            Some("goto") => {
+                self.advance()?;
+                Ok(Stat::Goto { label: self.name()? })
+            },
+            Some("break") => {
+                self.advance()?;
+                Ok(Stat::Break)
+            },
+            Some(word) => self.error(&format!("unexpected '{}'", word)),
+            None if self.isSymbol("::") => {
+                self.advance()?;
+                let name = self.name()?;
+                self.expectSymbol("::")?;
+                Ok(Stat::Label { name })
+            },
+            None => self.expStatement(),
+        }
+    }
+
+    /// A call, or an assignment to one or more variables.
+    fn expStatement(&mut self) -> ParseResult<Stat> {
+        let exp = self.suffixedExp()?;
+        if self.isSymbol("=") || self.isSymbol(",") {
+            let mut var_list = vec![exp];
+            while self.testSymbol(",")? {
+                var_list.push(self.suffixedExp()?);
+            }
+            self.expectSymbol("=")?;
+            for var in &var_list {
+                match var {
+                    Exp::Var(_) | Exp::Field{..} | Exp::Index{..} => {},
+                    _ => return self.error("cannot assign to this expression"),
+                }
+            }
+            let exp_list = self.expList()?;
+            return Ok(Stat::Assignment { var_list, exp_list });
+        }
+        match exp {
+            Exp::Call { function, arg_list } => Ok(Stat::FuncCall { function: *function, par_list: arg_list }),
+            Exp::MethodCall { object, method, arg_list } => Ok(Stat::MethodCall { object: *object, method, par_list: arg_list }),
+            _ => self.error("syntax error, expected a call or an assignment"),
+        }
+    }
+
+    fn expList(&mut self) -> ParseResult<Vec<Exp>> {
+        let mut exp_list = vec![self.exp()?];
+        while self.testSymbol(",")? {
+            exp_list.push(self.exp()?);
+        }
+        Ok(exp_list)
+    }
+
+    fn primaryExp(&mut self) -> ParseResult<Exp> {
+        if self.testSymbol("(")? {
+            let exp = self.exp()?;
+            self.expectSymbol(")")?;
+            return Ok(Exp::Paren(Box::new(exp)));
+        }
+        Ok(Exp::Var(self.name()?))
+    }
+
+    fn suffixedExp(&mut self) -> ParseResult<Exp> {
+        let mut exp = self.primaryExp()?;
+        loop {
+            if self.testSymbol(".")? {
+                exp = Exp::Field { prefix: Box::new(exp), name: self.name()? };
+            } else if self.testSymbol("[")? {
+                let key = self.exp()?;
+                self.expectSymbol("]")?;
+                exp = Exp::Index { prefix: Box::new(exp), key: Box::new(key) };
+            } else if self.testSymbol(":")? {
+                let method = self.name()?;
+                let arg_list = self.args()?;
+                exp = Exp::MethodCall { object: Box::new(exp), method, arg_list };
+            } else if self.isSymbol("(") || self.isSymbol("{") || matches!(self.token, Token::String(_)) {
+                let arg_list = self.args()?;
+                exp = Exp::Call { function: Box::new(exp), arg_list };
+            } else {
+                return Ok(exp);
+            }
+        }
+    }
+
+    fn args(&mut self) -> ParseResult<Vec<Exp>> {
+        if let Token::String(_) = self.token {
+            if let Token::String(value) = self.advance()? {
+                return Ok(vec![Exp::String(value)]);
+            }
+        }
+        if self.isSymbol("{") {
+            return Ok(vec![self.table()?]);
+        }
+        self.expectSymbol("(")?;
+        let arg_list = if self.isSymbol(")") { Vec::new() } else { self.expList()? };
+        self.expectSymbol(")")?;
+        Ok(arg_list)
+    }
+
+    /// A constructor becomes an `Array` when it only has positional fields and a `Table`
+    /// when every key is a string, anything else a `KeyedTable` with the positions as
+    /// integer keys.
+    fn table(&mut self) -> ParseResult<Exp> {
+        self.expectSymbol("{")?;
+        let mut fields: Vec<(Option<Exp>, Exp)> = Vec::new();
+        while !self.isSymbol("}") {
+            if self.testSymbol("[")? {
+                let key = self.exp()?;
+                self.expectSymbol("]")?;
+                self.expectSymbol("=")?;
+                fields.push((Some(key), self.exp()?));
+            } else if matches!(self.token, Token::Name(_)) && self.peek()? == &Token::Symbol("=") {
+                let key = self.name()?;
+                self.advance()?;
+                fields.push((Some(Exp::String(key)), self.exp()?));
+            } else {
+                fields.push((None, self.exp()?));
+            }
+            if !self.testSymbol(",")? && !self.testSymbol(";")? { break; }
+        }
+        self.expectSymbol("}")?;
+
+        if fields.iter().all(|(key, _)| key.is_none()) && !fields.is_empty() {
+            return Ok(Exp::Array { member_list: fields.into_iter().map(|(_, value)| value).collect() });
+        }
+        if fields.iter().all(|(key, _)| matches!(key, Some(Exp::String(_)))) {
+            return Ok(Exp::Table {
+                field_list: fields.into_iter()
+                    .map(|(key, value)| match key {
+                        Some(Exp::String(key)) => (key, value),
+                        _ => unreachable!(),
+                    })
+                    .collect(),
+            });
+        }
+        let mut position = 0;
+        Ok(Exp::KeyedTable {
+            field_list: fields.into_iter()
+                .map(|(key, value)| match key {
+                    Some(key) => (key, value),
+                    None => {
+                        position += 1;
+                        (Exp::Integer(position), value)
+                    },
+                })
+                .collect(),
+        })
+    }
+
+    fn simpleExp(&mut self) -> ParseResult<Exp> {
+        let exp = match &self.token {
+            Token::Integer(value) => Exp::Integer(*value),
+            Token::Number(value) => match Exp::number(*value) {
+                Ok(exp) => exp,
+                Err(err) => return self.error(&err.to_string()),
+            },
+            Token::String(value) => Exp::String(value.clone()),
+            Token::Keyword(word) if word == "nil" => Exp::Nil,
+            Token::Keyword(word) if word == "true" => Exp::Bool(true),
+            Token::Keyword(word) if word == "false" => Exp::Bool(false),
+            Token::Symbol("...") => Exp::Vararg,
+            Token::Symbol("{") => return self.table(),
+            Token::Keyword(word) if word == "function" => {
+                self.advance()?;
+                let (par_list, body) = self.funcBody()?;
+                return Ok(Exp::Function { par_list, body });
+            },
+            _ => return self.suffixedExp(),
+        };
+        self.advance()?;
+        Ok(exp)
+    }
+
+    fn subExp(&mut self, limit: u32) -> ParseResult<Exp> {
+        let unop = match &self.token {
+            Token::Keyword(word) if word == "not" => Some("not"),
+            Token::Symbol("-") => Some("-"),
+            Token::Symbol("#") => Some("#"),
+            _ => None,
+        };
+        let mut exp = match unop {
+            Some(op) => {
+                self.advance()?;
+                let right = self.subExp(UNARY_PRIORITY)?;
+                Exp::Unop { op: String::from(op), right: Box::new(right) }
+            },
+            None => self.simpleExp()?,
+        };
+        while let Some((op, left, right)) = binaryPriority(&self.token) {
+            if left <= limit { break; }
+            self.advance()?;
+            let right = self.subExp(right)?;
+            exp = Exp::binop(exp, op, right)?;
+        }
+        Ok(exp)
+    }
+
+    fn exp(&mut self) -> ParseResult<Exp> {
+        self.subExp(0)
+    }
+}
+
+fn parser(source: &str) -> ParseResult<Parser<'_>> {
+    let mut lexer = Lexer { source: source.as_bytes(), pos: 0, line: 1 };
+    // a leading #! line is skipped like the standalone interpreter does
+    if source.starts_with("#") {
+        while lexer.pos < lexer.source.len() && lexer.peekByte(0) != b'\n' { lexer.pos += 1; }
+    }
+    let token = lexer.next()?;
+    let line = lexer.line;
+    Ok(Parser { lexer, token, line, lookahead: None })
+}
+
+/// Parses a Lua 5.2 chunk.
+pub fn parse(source: &str) -> ParseResult<Block> {
+    let mut parser = parser(source)?;
+    let block = parser.block()?;
+    if parser.token != Token::Eof {
+        return parser.error(&format!("unexpected {:?}", parser.token));
+    }
+    Ok(block)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lua::{LuaSyntax, Layout, PrintStyle, PRETTY};
+
+    const MINIFIED: PrintStyle = PrintStyle { layout: Layout::minified, sort_keys: false };
+
+    /// Parses `source`, and checks that printing it in either layout reads back as the
+    /// same chunk.
+    fn roundTrip(source: &str) -> Block {
+        let block = parse(source).unwrap_or_else(|err| panic!("{}\n{}", err, source));
+        for style in &[PRETTY, MINIFIED] {
+            let printed = block.print(style);
+            let reparsed = parse(&printed).unwrap_or_else(|err| panic!("{}\n{}", err, printed));
+            assert_eq!(reparsed.print(style), printed);
+        }
+        block
+    }
+
+    fn returned(block: &Block) -> &[Exp] {
+        match &block.last_stat {
+            Some(LastStat::Return { exp_list }) => exp_list,
+            None => panic!("no return"),
+        }
+    }
+
+    fn strings(source: &str) -> Vec<String> {
+        returned(&roundTrip(source)).iter()
+            .map(|exp| match exp {
+                Exp::String(value) => value.clone(),
+                _ => panic!("not a string"),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn stringsAndEscapes() {
+        let values = strings(r#"return "a\"b\\c", 'it\'s "quoted"', "\n\r\t\a\b\f\v", "\65\0669\x43", "a\z
+            b", "é\0end""#);
+        assert_eq!(values, [
+            "a\"b\\c",
+            "it's \"quoted\"",
+            "\n\r\t\u{7}\u{8}\u{c}\u{b}",
+            "AB9C",
+            "ab",
+            "é\0end",
+        ]);
+        assert!(parse(r#"return "unfinished"#).is_err());
+        assert!(parse(r#"return "\q""#).is_err());
+        assert!(parse(r#"return "\256""#).is_err());
+    }
+
+    #[test]
+    fn longBrackets() {
+        let values = strings("return [[plain]], [==[with ]] and ]=] inside]==], [[\nfirst newline skipped\n]], [[]]");
+        assert_eq!(values, ["plain", "with ]] and ]=] inside", "first newline skipped\n", ""]);
+        let block = roundTrip("--[==[ a long\ncomment ]] ]==] return 1 -- a line comment");
+        assert_eq!(returned(&block).len(), 1);
+        assert!(parse("return [==[unfinished]=]").is_err());
+    }
+
+    #[test]
+    fn numbers() {
+        let block = roundTrip("return 1, 0x10, 0XfF, 1.5, .5, 3., 1e3, 2E-2, 9223372036854775807, 9223372036854775808");
+        let printed: Vec<String> = returned(&block).iter().map(|exp| exp.print(&MINIFIED)).collect();
+        assert_eq!(printed, ["1", "16", "255", "1.5", "0.5", "3.0", "1000.0", "0.02", "9223372036854775807", "9.223372036854776e18"]);
+        assert!(parse("return 1e999").is_err());
+        assert!(parse("return 0x1p4").is_err());
+        assert!(parse("return 1e").is_err());
+    }
+
+    #[test]
+    fn operatorPrecedence() {
+        let cases = [
+            ("return 1 + 2 * 3", "return 1 + 2 * 3"),
+            ("return (1 + 2) * 3", "return (1 + 2) * 3"),
+            ("return 1 - (2 - 3)", "return 1 - (2 - 3)"),
+            ("return 1 - 2 - 3", "return 1 - 2 - 3"),
+            ("return 2 ^ 3 ^ 2", "return 2 ^ 3 ^ 2"),
+            ("return -2 ^ 2", "return -2 ^ 2"),
+            ("return 2 ^ -3", "return 2 ^ (-3)"),
+            ("return a .. b .. c", "return a .. b .. c"),
+            ("return a or b and c", "return a or b and c"),
+            ("return not a == b", "return not a == b"),
+            ("return #t + 1 < 3", "return #t + 1 < 3"),
+            ("return - -x", "return - -x"),
+        ];
+        for (source, expected) in &cases {
+            assert_eq!(roundTrip(source).print(&PRETTY).trim_end(), *expected);
+        }
+    }
+
+    #[test]
+    fn tableForms() {
+        let block = roundTrip(r#"return {}, {1, "two", {3}}, {a = 1, ["b c"] = 2, ["end"] = 3}, {1, x = 2, [3] = 4; 5}, {[f()] = true}"#);
+        let exps = returned(&block);
+        assert!(matches!(&exps[0], Exp::Table { field_list } if field_list.is_empty()));
+        assert!(matches!(&exps[1], Exp::Array { member_list } if member_list.len() == 3));
+        match &exps[2] {
+            Exp::Table { field_list } => {
+                let keys: Vec<&str> = field_list.iter().map(|(key, _)| &key[..]).collect();
+                assert_eq!(keys, ["a", "b c", "end"]);
+            },
+            _ => panic!("not a table"),
+        }
+        match &exps[3] {
+            Exp::KeyedTable { field_list } => {
+                let keys: Vec<String> = field_list.iter().map(|(key, _)| key.print(&MINIFIED)).collect();
+                assert_eq!(keys, ["1", "\"x\"", "3", "2"]);
+            },
+            _ => panic!("not a keyed table"),
+        }
+        assert!(matches!(&exps[4], Exp::KeyedTable { .. }));
+    }
+
+    #[test]
+    fn everyStatement() {
+        let block = roundTrip(r#"
+            local a, b = 1, 2
+            local c
+            a, t.x, t[1] = b, c, nil
+            print("hi")
+            io.write "hi"
+            obj:method({}, ...)
+            do local d = 1 end
+            while a < 10 do a = a + 1 end
+            repeat a = a - 1 until a == 0
+            if a then f() elseif b then g() elseif c then h() else i() end
+            if a then end
+            for i = 1, 10 do end
+            for i = 10, 1, -1 do f(i) end
+            for k, v in pairs(t) do f(k, v) end
+            function m.n:o(p, ...) return p end
+            function q() end
+            local function r(s) return end
+            goto continue
+            ::continue::
+            local e = function(...) return ... end
+            return a, b
+        "#);
+        let kinds: Vec<&str> = block.stats.iter()
+            .map(|stat| match stat {
+                Stat::Assignment { .. } => "assignment",
+                Stat::Local { .. } => "local",
+                Stat::FuncCall { .. } => "call",
+                Stat::MethodCall { .. } => "method call",
+                Stat::Do { .. } => "do",
+                Stat::While { .. } => "while",
+                Stat::Repeat { .. } => "repeat",
+                Stat::If { .. } => "if",
+                Stat::NumericFor { .. } => "numeric for",
+                Stat::GenericFor { .. } => "generic for",
+                Stat::Function { .. } => "function",
+                Stat::LocalFunction { .. } => "local function",
+                Stat::Goto { .. } => "goto",
+                Stat::Label { .. } => "label",
+                Stat::Break => "break",
+            })
+            .collect();
+        assert_eq!(kinds, [
+            "local", "local", "assignment", "call", "call", "method call", "do", "while", "repeat", "if", "if",
+            "numeric for", "numeric for", "generic for", "function", "function", "local function", "goto", "label",
+            "local",
+        ]);
+        assert_eq!(returned(&block).len(), 2);
+        assert!(parse("return 1 x = 2").is_err());
+        assert!(parse("f() = 1").is_err());
+        assert!(parse("x").is_err());
+    }
+
+    #[test]
+    fn breakIsAStatement() {
+        let block = roundTrip("while true do break; x() end for i = 1, 2 do if i then break end end break");
+        match &block.stats[0] {
+            Stat::While { body, .. } => {
+                assert!(matches!(body.stats[..], [Stat::Break, Stat::FuncCall { .. }]));
+            },
+            _ => panic!("not a while loop"),
+        }
+        assert!(matches!(block.stats.last(), Some(Stat::Break)));
+        assert_eq!(
+            parse("while true do break; x() end").unwrap().print(&MINIFIED),
+            "while true do break;x() end",
+        );
+    }
+
+    #[test]
+    fn parenthesesAreKept() {
+        let block = roundTrip("local a = (f()) local b = (...) local c = ((1)) return (g)(), (t).x");
+        let printed = block.print(&MINIFIED);
+        assert_eq!(printed, "local a=(f());local b=(...);local c=((1));return (g)(),(t).x");
+        assert!(matches!(&block.stats[0], Stat::Local { exp_list, .. } if matches!(exp_list[..], [Exp::Paren(_)])));
+        assert!(parse("(a) = 1").is_err());
+    }
+}
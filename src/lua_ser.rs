@@ -0,0 +1,507 @@
+use std::error::Error;
+use std::fmt;
+use serde::ser::{self, Serialize};
+use crate::lua::Exp;
+
+/// Why a value couldn't be turned into Lua.
+#[derive(Debug)]
+pub struct SerializeError(String);
+
+impl fmt::Display for SerializeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl Error for SerializeError {}
+
+impl ser::Error for SerializeError {
+    fn custom<T: fmt::Display>(msg: T) -> SerializeError {
+        SerializeError(msg.to_string())
+    }
+}
+
+pub type SerializeResult = Result<Exp, SerializeError>;
+
+/// A value written as a Lua expression by itself rather than through serde, for the
+/// prototypes, whose fields hold expressions such as `0.5 * scale` that serde has no way
+/// to carry. `None` becomes nil, which tables leave out and sequences refuse.
+pub trait ToExp {
+    fn toExp(&self) -> SerializeResult;
+}
+
+/// An expression written into the output as is, for values that aren't data.
+#[derive(Clone)]
+pub struct RawExp(pub Exp);
+
+impl ToExp for RawExp {
+    fn toExp(&self) -> SerializeResult { Ok(self.0.clone()) }
+}
+
+impl ToExp for bool {
+    fn toExp(&self) -> SerializeResult { Ok(Exp::Bool(*self)) }
+}
+
+impl ToExp for i32 {
+    fn toExp(&self) -> SerializeResult { Ok(Exp::Integer((*self).into())) }
+}
+
+impl ToExp for u32 {
+    fn toExp(&self) -> SerializeResult { Ok(Exp::Integer((*self).into())) }
+}
+
+impl ToExp for f64 {
+    fn toExp(&self) -> SerializeResult {
+        Exp::number(*self).map_err(|err| SerializeError(err.to_string()))
+    }
+}
+
+impl ToExp for String {
+    fn toExp(&self) -> SerializeResult { Ok(Exp::String(self.clone())) }
+}
+
+impl<T: ToExp> ToExp for Option<T> {
+    fn toExp(&self) -> SerializeResult {
+        match self {
+            Some(value) => value.toExp(),
+            None => Ok(Exp::Nil),
+        }
+    }
+}
+
+impl<T: ToExp + ?Sized> ToExp for Box<T> {
+    fn toExp(&self) -> SerializeResult { (**self).toExp() }
+}
+
+impl<T: ToExp> ToExp for [T] {
+    fn toExp(&self) -> SerializeResult {
+        let member_list = self.iter()
+            .map(|value| arrayMember(value.toExp()?))
+            .collect::<Result<_, _>>()?;
+        Ok(Exp::Array { member_list })
+    }
+}
+
+impl<T: ToExp> ToExp for Vec<T> {
+    fn toExp(&self) -> SerializeResult { self[..].toExp() }
+}
+
+impl<T: ToExp, const N: usize> ToExp for [T; N] {
+    fn toExp(&self) -> SerializeResult { self[..].toExp() }
+}
+
+fn arrayMember(exp: Exp) -> SerializeResult {
+    match exp {
+        Exp::Nil => Err(SerializeError(String::from("nil in a sequence leaves a hole in the lua array"))),
+        exp => Ok(exp),
+    }
+}
+
+/// Fields of a table built one at a time for a `ToExp`, leaving out the nil ones.
+#[derive(Default)]
+pub struct TableBuilder {
+    field_list: Vec<(String, Exp)>,
+}
+
+impl TableBuilder {
+    fn push(&mut self, key: String, exp: Exp) -> Result<(), SerializeError> {
+        if self.field_list.iter().any(|(old_key, _)| *old_key == key) {
+            return Err(SerializeError(format!("field {} is set twice", key)));
+        }
+        self.field_list.push((key, exp));
+        Ok(())
+    }
+
+    pub fn field<T: ToExp + ?Sized>(&mut self, key: &str, value: &T) -> Result<(), SerializeError> {
+        match value.toExp()? {
+            Exp::Nil => Ok(()),
+            exp => self.push(String::from(key), exp),
+        }
+    }
+
+    /// Adds the fields of `value`, which has to be a table, like `#[serde(flatten)]`.
+    pub fn flatten<T: ToExp + ?Sized>(&mut self, value: &T) -> Result<(), SerializeError> {
+        match value.toExp()? {
+            Exp::Table { field_list } => field_list.into_iter().try_for_each(|(key, exp)| self.push(key, exp)),
+            Exp::Nil => Ok(()),
+            _ => Err(SerializeError(String::from("only a table can be flattened into another"))),
+        }
+    }
+
+    pub fn build(self) -> Exp {
+        Exp::Table { field_list: self.field_list }
+    }
+}
+
+/// `value` as a Lua expression: structs and maps become tables, sequences and tuples
+/// arrays, unit enum variants their name as a string. Fields that are `None` are left
+/// out of their table, while a `None` in a sequence is an error.
+pub fn toExp<T: Serialize + ?Sized>(value: &T) -> Result<Exp, Box<dyn Error + Send + Sync>> {
+    Ok(value.serialize(ExpSerializer)?)
+}
+
+fn variantTable(variant: &str, value: Exp) -> Exp {
+    Exp::Table { field_list: vec![(String::from(variant), value)] }
+}
+
+struct ExpSerializer;
+
+impl ser::Serializer for ExpSerializer {
+    type Ok = Exp;
+    type Error = SerializeError;
+    type SerializeSeq = SeqSerializer;
+    type SerializeTuple = SeqSerializer;
+    type SerializeTupleStruct = SeqSerializer;
+    type SerializeTupleVariant = SeqSerializer;
+    type SerializeMap = MapSerializer;
+    type SerializeStruct = MapSerializer;
+    type SerializeStructVariant = MapSerializer;
+
+    fn serialize_bool(self, v: bool) -> SerializeResult { Ok(Exp::Bool(v)) }
+    fn serialize_i8(self, v: i8) -> SerializeResult { Ok(Exp::Integer(v.into())) }
+    fn serialize_i16(self, v: i16) -> SerializeResult { Ok(Exp::Integer(v.into())) }
+    fn serialize_i32(self, v: i32) -> SerializeResult { Ok(Exp::Integer(v.into())) }
+    fn serialize_i64(self, v: i64) -> SerializeResult { Ok(Exp::Integer(v)) }
+    fn serialize_u8(self, v: u8) -> SerializeResult { Ok(Exp::Integer(v.into())) }
+    fn serialize_u16(self, v: u16) -> SerializeResult { Ok(Exp::Integer(v.into())) }
+    fn serialize_u32(self, v: u32) -> SerializeResult { Ok(Exp::Integer(v.into())) }
+
+    fn serialize_u64(self, v: u64) -> SerializeResult {
+        if v > i64::MAX as u64 {
+            return Err(SerializeError(format!("{} doesn't fit a lua integer", v)));
+        }
+        Ok(Exp::Integer(v as i64))
+    }
+
+    fn serialize_f32(self, v: f32) -> SerializeResult {
+        // through the shortest decimal form, so 0.1f32 stays 0.1 rather than its exact value
+        self.serialize_f64(v.to_string().parse().unwrap_or(v as f64))
+    }
+
+    fn serialize_f64(self, v: f64) -> SerializeResult {
+        Exp::number(v).map_err(|err| SerializeError(err.to_string()))
+    }
+
+    fn serialize_char(self, v: char) -> SerializeResult { Ok(Exp::String(v.to_string())) }
+    fn serialize_str(self, v: &str) -> SerializeResult { Ok(Exp::String(String::from(v))) }
+
+    fn serialize_bytes(self, v: &[u8]) -> SerializeResult {
+        Ok(Exp::Array { member_list: v.iter().map(|byte| Exp::Integer((*byte).into())).collect() })
+    }
+
+    fn serialize_none(self) -> SerializeResult { Ok(Exp::Nil) }
+
+    fn serialize_some<T: Serialize + ?Sized>(self, value: &T) -> SerializeResult {
+        value.serialize(self)
+    }
+
+    fn serialize_unit(self) -> SerializeResult { Ok(Exp::Nil) }
+    fn serialize_unit_struct(self, _name: &'static str) -> SerializeResult { Ok(Exp::Nil) }
+
+    fn serialize_unit_variant(self, _name: &'static str, _index: u32, variant: &'static str) -> SerializeResult {
+        Ok(Exp::String(String::from(variant)))
+    }
+
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(self, _name: &'static str, value: &T) -> SerializeResult {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+        value: &T,
+    ) -> SerializeResult {
+        Ok(variantTable(variant, value.serialize(self)?))
+    }
+
+    fn serialize_seq(self, len: Option<usize>) -> Result<SeqSerializer, SerializeError> {
+        Ok(SeqSerializer { variant: None, member_list: Vec::with_capacity(len.unwrap_or(0)) })
+    }
+
+    fn serialize_tuple(self, len: usize) -> Result<SeqSerializer, SerializeError> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_struct(self, _name: &'static str, len: usize) -> Result<SeqSerializer, SerializeError> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+        len: usize,
+    ) -> Result<SeqSerializer, SerializeError> {
+        Ok(SeqSerializer { variant: Some(variant), member_list: Vec::with_capacity(len) })
+    }
+
+    fn serialize_map(self, len: Option<usize>) -> Result<MapSerializer, SerializeError> {
+        Ok(MapSerializer { variant: None, field_list: Vec::with_capacity(len.unwrap_or(0)), key: None })
+    }
+
+    fn serialize_struct(self, _name: &'static str, len: usize) -> Result<MapSerializer, SerializeError> {
+        self.serialize_map(Some(len))
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+        len: usize,
+    ) -> Result<MapSerializer, SerializeError> {
+        Ok(MapSerializer { variant: Some(variant), field_list: Vec::with_capacity(len), key: None })
+    }
+}
+
+struct SeqSerializer {
+    variant: Option<&'static str>,
+    member_list: Vec<Exp>,
+}
+
+impl SeqSerializer {
+    fn push<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), SerializeError> {
+        self.member_list.push(arrayMember(value.serialize(ExpSerializer)?)?);
+        Ok(())
+    }
+
+    fn finish(self) -> SerializeResult {
+        let array = Exp::Array { member_list: self.member_list };
+        Ok(match self.variant {
+            Some(variant) => variantTable(variant, array),
+            None => array,
+        })
+    }
+}
+
+impl ser::SerializeSeq for SeqSerializer {
+    type Ok = Exp;
+    type Error = SerializeError;
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), SerializeError> { self.push(value) }
+    fn end(self) -> SerializeResult { self.finish() }
+}
+
+impl ser::SerializeTuple for SeqSerializer {
+    type Ok = Exp;
+    type Error = SerializeError;
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), SerializeError> { self.push(value) }
+    fn end(self) -> SerializeResult { self.finish() }
+}
+
+impl ser::SerializeTupleStruct for SeqSerializer {
+    type Ok = Exp;
+    type Error = SerializeError;
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), SerializeError> { self.push(value) }
+    fn end(self) -> SerializeResult { self.finish() }
+}
+
+impl ser::SerializeTupleVariant for SeqSerializer {
+    type Ok = Exp;
+    type Error = SerializeError;
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), SerializeError> { self.push(value) }
+    fn end(self) -> SerializeResult { self.finish() }
+}
+
+struct MapSerializer {
+    variant: Option<&'static str>,
+    field_list: Vec<(Exp, Exp)>,
+    /// Key waiting for its value.
+    key: Option<Exp>,
+}
+
+impl MapSerializer {
+    fn insert(&mut self, key: Exp, value: Exp) {
+        // None fields are left out rather than written as nil
+        if let Exp::Nil = value { return; }
+        self.field_list.push((key, value));
+    }
+
+    /// A `Table` when every key is a string, which is what prototypes use.
+    fn finish(self) -> SerializeResult {
+        let table = if self.field_list.iter().all(|(key, _)| matches!(key, Exp::String(_))) {
+            Exp::Table {
+                field_list: self.field_list.into_iter()
+                    .map(|(key, value)| match key {
+                        Exp::String(key) => (key, value),
+                        _ => unreachable!(),
+                    })
+                    .collect(),
+            }
+        } else {
+            Exp::KeyedTable { field_list: self.field_list }
+        };
+        Ok(match self.variant {
+            Some(variant) => variantTable(variant, table),
+            None => table,
+        })
+    }
+}
+
+impl ser::SerializeMap for MapSerializer {
+    type Ok = Exp;
+    type Error = SerializeError;
+
+    fn serialize_key<T: Serialize + ?Sized>(&mut self, key: &T) -> Result<(), SerializeError> {
+        match key.serialize(ExpSerializer)? {
+            Exp::Nil => Err(SerializeError(String::from("lua table keys can't be nil"))),
+            key => {
+                self.key = Some(key);
+                Ok(())
+            },
+        }
+    }
+
+    fn serialize_value<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), SerializeError> {
+        let key = self.key.take().expect("serialize_value called before serialize_key");
+        let value = value.serialize(ExpSerializer)?;
+        self.insert(key, value);
+        Ok(())
+    }
+
+    fn end(self) -> SerializeResult { self.finish() }
+}
+
+impl ser::SerializeStruct for MapSerializer {
+    type Ok = Exp;
+    type Error = SerializeError;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, key: &'static str, value: &T) -> Result<(), SerializeError> {
+        let value = value.serialize(ExpSerializer)?;
+        self.insert(Exp::String(String::from(key)), value);
+        Ok(())
+    }
+
+    fn end(self) -> SerializeResult { self.finish() }
+}
+
+impl ser::SerializeStructVariant for MapSerializer {
+    type Ok = Exp;
+    type Error = SerializeError;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, key: &'static str, value: &T) -> Result<(), SerializeError> {
+        let value = value.serialize(ExpSerializer)?;
+        self.insert(Exp::String(String::from(key)), value);
+        Ok(())
+    }
+
+    fn end(self) -> SerializeResult { self.finish() }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::Serialize;
+    use crate::lua::{LuaSyntax, PRETTY};
+
+    struct Sheet {
+        width: i32,
+        scale: RawExp,
+        shift: [RawExp; 2],
+        tint: Option<RawExp>,
+        hr_version: Option<Box<Sheet>>,
+    }
+
+    impl ToExp for Sheet {
+        fn toExp(&self) -> SerializeResult {
+            let mut table = TableBuilder::default();
+            table.field("width", &self.width)?;
+            table.field("scale", &self.scale)?;
+            table.field("shift", &self.shift)?;
+            table.field("tint", &self.tint)?;
+            table.field("hr_version", &self.hr_version)?;
+            Ok(table.build())
+        }
+    }
+
+    fn sheet(width: i32, hr_version: Option<Sheet>) -> Sheet {
+        Sheet {
+            width,
+            scale: RawExp(Exp::binop(Exp::number(0.5).unwrap(), "*", Exp::var("scale").unwrap()).unwrap()),
+            shift: [RawExp(Exp::Integer(-3)), RawExp(Exp::number(0.1).unwrap())],
+            tint: None,
+            hr_version: hr_version.map(Box::new),
+        }
+    }
+
+    #[test]
+    fn rawExpressionsComeThroughAsWritten() {
+        assert_eq!(
+            sheet(64, None).toExp().unwrap().print(&PRETTY),
+            "{\n  width = 64,\n  scale = 0.5 * scale,\n  shift = {\n    -3,\n    0.1\n  }\n}",
+        );
+    }
+
+    #[test]
+    fn nestedRawExpressions() {
+        let mut hd = sheet(128, None);
+        hd.tint = Some(RawExp(Exp::var("tint").unwrap()));
+        let exp = sheet(64, Some(hd)).toExp().unwrap();
+        let hr_version = match &exp {
+            Exp::Table { field_list } => &field_list.last().unwrap().1,
+            _ => panic!("not a table"),
+        };
+        assert_eq!(
+            hr_version.print(&PRETTY),
+            "{\n  width = 128,\n  scale = 0.5 * scale,\n  shift = {\n    -3,\n    0.1\n  },\n  tint = tint\n}",
+        );
+    }
+
+    #[derive(Serialize)]
+    struct Icon {
+        icon: String,
+        icon_mipmaps: Option<u32>,
+    }
+
+    #[test]
+    fn noneIsLeftOutOfTablesButRefusedInSequences() {
+        let icon = Icon { icon: String::from("a.png"), icon_mipmaps: None };
+        assert_eq!(toExp(&icon).unwrap().print(&PRETTY), "{\n  icon = \"a.png\"\n}");
+        assert!(toExp(&vec![Some(1), None, Some(3)]).is_err());
+        assert!(toExp(&(1, None::<i32>)).is_err());
+
+        let tints = vec![Some(RawExp(Exp::var("tint").unwrap())), None];
+        assert!(tints.toExp().is_err());
+        let mut table = TableBuilder::default();
+        table.field("tint", &tints[1]).unwrap();
+        assert_eq!(table.build().print(&PRETTY), "{}");
+    }
+
+    #[derive(Serialize)]
+    struct Size {
+        width: i32,
+        height: i32,
+    }
+
+    #[derive(Serialize)]
+    struct Frame {
+        name: &'static str,
+        #[serde(flatten)]
+        size: Size,
+        count: Option<i32>,
+    }
+
+    #[test]
+    fn flattenedStructs() {
+        let frame = Frame { name: "a", size: Size { width: 2, height: 3 }, count: Some(4) };
+        assert_eq!(
+            toExp(&frame).unwrap().print(&PRETTY),
+            "{\n  name = \"a\",\n  width = 2,\n  height = 3,\n  count = 4\n}",
+        );
+
+        let mut table = TableBuilder::default();
+        table.field("name", &String::from("a")).unwrap();
+        table.flatten(&sheet(32, None)).unwrap();
+        table.flatten(&None::<Sheet>).unwrap();
+        assert_eq!(
+            table.build().print(&PRETTY),
+            "{\n  name = \"a\",\n  width = 32,\n  scale = 0.5 * scale,\n  shift = {\n    -3,\n    0.1\n  }\n}",
+        );
+        let mut table = TableBuilder::default();
+        table.field("width", &64).unwrap();
+        assert!(table.flatten(&sheet(32, None)).is_err());
+        assert!(TableBuilder::default().flatten(&RawExp(Exp::Integer(1))).is_err());
+    }
+}
@@ -10,18 +10,52 @@ mod anim;
 mod sprite_maker;
 mod sprite_config;
 mod lua;
+mod lua_parser;
+mod lua_ser;
+mod prototype;
 mod factorio_anim_writer;
+mod run_config;
+mod sheet_layout;
+mod debug_overlay;
+mod direction;
+mod dat;
+mod unit_writer;
+mod icon_writer;
+mod ddsgrp;
+mod gui_writer;
+mod sheet_checker;
 
 use std::error::Error;
 use std::env::args;
 
-fn main() -> Result<(), Box<dyn Error>> {
-    let mut storage = casc::CascStorage::open("/home/henryj/Games/battlenet/drive_c/Program Files (x86)/StarCraft/")?;
+fn main() -> Result<(), Box<dyn Error + Send + Sync>> {
     let argv: Vec<String> = args().collect();
-    let working_dir = String::from(".");
-    let output_dir = if argv.len() > 1 {&argv[1]} else {&working_dir};
-    factorio_anim_writer::writeAnimations(&mut storage,&sprite_config::getConfig(), output_dir)?;
+    let config = run_config::RunConfig::fromArgs(&argv[1..])?;
+    let mut storage = casc::CascStorage::open(&config.storage_path)?;
+    let metadata = sprite_config::getConfig();
+    let entries = factorio_anim_writer::writeAnimations(
+        &mut storage,
+        &metadata,
+        &sprite_config::getCompositions(),
+        &sprite_config::getIcons(),
+        &config,
+    )?;
+    if config.write_units {
+        unit_writer::writeUnits(&mut storage, &sprite_config::getUnits(), &metadata, &entries, &config)?;
+    }
+    if config.write_gui_sprites {
+        gui_writer::writeGuiSprites(&mut storage, &sprite_config::getGuiSprites(), &config)?;
+    }
     storage.close()?;
+    if config.check_sheets {
+        let problems = sheet_checker::checkSheets(&config)?;
+        for problem in &problems {
+            println!("Error: {}", problem);
+        }
+        if !problems.is_empty() {
+            return Err(format!("{} problems in anim.lua", problems.len()).into());
+        }
+    }
 
     Ok(())
 }
@@ -0,0 +1,410 @@
+use std::error::Error;
+use crate::lua_ser::{RawExp, SerializeResult, TableBuilder, ToExp};
+
+/// Largest frame width or height Factorio accepts.
+pub const MAX_SPRITE_SIZE: i32 = 4096;
+/// Largest width or height of a sheet file Factorio loads.
+pub const MAX_IMAGE_SIZE: i32 = 8192;
+/// Longest `frame_sequence` Factorio accepts.
+pub const MAX_FRAME_SEQUENCE_LENGTH: usize = 255;
+
+type CheckResult = Result<(), Box<dyn Error + Send + Sync>>;
+
+/// One file of a sheet split into `stripes`.
+#[derive(Clone)]
+pub struct Stripe {
+    pub filename: String,
+    pub width_in_frames: i32,
+    pub height_in_frames: i32,
+}
+
+/// Where the frames of an animation are: exactly one of `filename`, `filenames` and
+/// `stripes` is set, the line fields only without `stripes`.
+#[derive(Clone)]
+pub struct SheetFiles {
+    pub filename: Option<String>,
+    pub filenames: Option<Vec<String>>,
+    pub stripes: Option<Vec<Stripe>>,
+    pub slice: Option<i32>,
+    pub line_length: Option<i32>,
+    pub lines_per_file: Option<i32>,
+    pub width: i32,
+    pub height: i32,
+    /// Not set for a `RotatedSprite`, which has one frame per direction.
+    pub frame_count: Option<i32>,
+}
+
+/// How a sheet is blended, set by its preset.
+#[derive(Clone, Default)]
+pub struct BlendFields {
+    pub draw_as_glow: Option<bool>,
+    pub flags: Option<Vec<String>>,
+    pub tint: Option<RawExp>,
+}
+
+/// How the frames of an animation are drawn and played. The animation fields aren't set
+/// for a `RotatedSprite`.
+#[derive(Clone)]
+pub struct DrawFields {
+    pub shift: [RawExp; 2],
+    pub animation_speed: Option<f64>,
+    pub draw_as_shadow: bool,
+    pub run_mode: Option<String>,
+    pub scale: RawExp,
+    pub frame_sequence: Option<Vec<i32>>,
+    pub blend: BlendFields,
+}
+
+#[derive(Clone)]
+pub struct Animation {
+    pub files: SheetFiles,
+    pub draw: DrawFields,
+    pub hr_version: Option<Box<Animation>>,
+}
+
+/// Also used for a `RotatedSprite`, which only leaves out the animation fields.
+#[derive(Clone)]
+pub struct RotatedAnimation {
+    pub files: SheetFiles,
+    pub direction_count: i32,
+    pub draw: DrawFields,
+    pub hr_version: Option<Box<RotatedAnimation>>,
+}
+
+/// A list of animations of which the game picks one, written as a plain array.
+#[derive(Clone)]
+pub struct AnimationVariations(pub Vec<Animation>);
+
+#[derive(Clone)]
+pub struct Sprite {
+    pub filename: String,
+    pub x: u32,
+    pub y: u32,
+    pub width: i32,
+    pub height: i32,
+    pub shift: [RawExp; 2],
+    pub draw_as_shadow: bool,
+    pub scale: RawExp,
+    pub blend: BlendFields,
+    pub hr_version: Option<Box<Sprite>>,
+}
+
+#[derive(Clone)]
+pub struct Sprite4Way {
+    pub north: Sprite,
+    pub east: Sprite,
+    pub south: Sprite,
+    pub west: Sprite,
+}
+
+impl ToExp for Stripe {
+    fn toExp(&self) -> SerializeResult {
+        let mut table = TableBuilder::default();
+        table.field("filename", &self.filename)?;
+        table.field("width_in_frames", &self.width_in_frames)?;
+        table.field("height_in_frames", &self.height_in_frames)?;
+        Ok(table.build())
+    }
+}
+
+impl ToExp for SheetFiles {
+    fn toExp(&self) -> SerializeResult {
+        let mut table = TableBuilder::default();
+        table.field("filename", &self.filename)?;
+        table.field("filenames", &self.filenames)?;
+        table.field("stripes", &self.stripes)?;
+        table.field("slice", &self.slice)?;
+        table.field("line_length", &self.line_length)?;
+        table.field("lines_per_file", &self.lines_per_file)?;
+        table.field("width", &self.width)?;
+        table.field("height", &self.height)?;
+        table.field("frame_count", &self.frame_count)?;
+        Ok(table.build())
+    }
+}
+
+impl ToExp for BlendFields {
+    fn toExp(&self) -> SerializeResult {
+        let mut table = TableBuilder::default();
+        table.field("draw_as_glow", &self.draw_as_glow)?;
+        table.field("flags", &self.flags)?;
+        table.field("tint", &self.tint)?;
+        Ok(table.build())
+    }
+}
+
+impl ToExp for DrawFields {
+    fn toExp(&self) -> SerializeResult {
+        let mut table = TableBuilder::default();
+        table.field("shift", &self.shift)?;
+        table.field("animation_speed", &self.animation_speed)?;
+        table.field("draw_as_shadow", &self.draw_as_shadow)?;
+        table.field("run_mode", &self.run_mode)?;
+        table.field("scale", &self.scale)?;
+        table.field("frame_sequence", &self.frame_sequence)?;
+        table.flatten(&self.blend)?;
+        Ok(table.build())
+    }
+}
+
+impl ToExp for Animation {
+    fn toExp(&self) -> SerializeResult {
+        let mut table = TableBuilder::default();
+        table.flatten(&self.files)?;
+        table.flatten(&self.draw)?;
+        table.field("hr_version", &self.hr_version)?;
+        Ok(table.build())
+    }
+}
+
+impl ToExp for RotatedAnimation {
+    fn toExp(&self) -> SerializeResult {
+        let mut table = TableBuilder::default();
+        table.flatten(&self.files)?;
+        table.field("direction_count", &self.direction_count)?;
+        table.flatten(&self.draw)?;
+        table.field("hr_version", &self.hr_version)?;
+        Ok(table.build())
+    }
+}
+
+impl ToExp for AnimationVariations {
+    fn toExp(&self) -> SerializeResult { self.0.toExp() }
+}
+
+impl ToExp for Sprite {
+    fn toExp(&self) -> SerializeResult {
+        let mut table = TableBuilder::default();
+        table.field("filename", &self.filename)?;
+        table.field("x", &self.x)?;
+        table.field("y", &self.y)?;
+        table.field("width", &self.width)?;
+        table.field("height", &self.height)?;
+        table.field("shift", &self.shift)?;
+        table.field("draw_as_shadow", &self.draw_as_shadow)?;
+        table.field("scale", &self.scale)?;
+        table.flatten(&self.blend)?;
+        table.field("hr_version", &self.hr_version)?;
+        Ok(table.build())
+    }
+}
+
+impl ToExp for Sprite4Way {
+    fn toExp(&self) -> SerializeResult {
+        let mut table = TableBuilder::default();
+        for (direction, sprite) in self.directions().iter() {
+            table.field(direction, *sprite)?;
+        }
+        Ok(table.build())
+    }
+}
+
+fn checkSize(width: i32, height: i32) -> CheckResult {
+    if width < 1 || height < 1 || width > MAX_SPRITE_SIZE || height > MAX_SPRITE_SIZE {
+        return Err(format!("size {}x{} is outside 1..={}", width, height, MAX_SPRITE_SIZE).into());
+    }
+    Ok(())
+}
+
+impl SheetFiles {
+    /// Each file is cut into lines of `slice` frames, counted as `line_length`.
+    fn checkSlice(&self, line_length: i32) -> CheckResult {
+        if self.slice != Some(line_length) {
+            return Err(format!("slice {:?} differs from line_length {}", self.slice, line_length).into());
+        }
+        Ok(())
+    }
+
+    /// Checks that a file of `columns` by `rows` frames is small enough to load.
+    fn checkSheetSize(&self, columns: i32, rows: i32) -> CheckResult {
+        let (width, height) = (columns as i64 * self.width as i64, rows as i64 * self.height as i64);
+        if width > MAX_IMAGE_SIZE as i64 || height > MAX_IMAGE_SIZE as i64 {
+            return Err(format!(
+                "{}x{} frames of {}x{} make a {}x{} file, more than {} pixels across",
+                columns, rows, self.width, self.height, width, height, MAX_IMAGE_SIZE,
+            ).into());
+        }
+        Ok(())
+    }
+
+    /// Checks that `frames` frames fit into the files.
+    fn validate(&self, frames: i32) -> CheckResult {
+        checkSize(self.width, self.height)?;
+        let line_length = self.line_length.filter(|&line_length| line_length > 0);
+        match (&self.filename, &self.filenames, &self.stripes) {
+            (Some(_), None, None) => {
+                if self.lines_per_file.is_some() {
+                    return Err("lines_per_file only applies to filenames".into());
+                }
+                let line_length = match line_length {
+                    Some(line_length) => line_length,
+                    None if frames == 1 && self.line_length.is_none() && self.slice.is_none() => return Ok(()),
+                    None => return Err(format!("a sheet of {} frames needs a positive line_length", frames).into()),
+                };
+                self.checkSlice(line_length)?;
+                self.checkSheetSize(line_length, (frames + line_length - 1) / line_length)?;
+            }
+            (None, Some(filenames), None) => {
+                let (line_length, lines_per_file) = match (line_length, self.lines_per_file) {
+                    (Some(line_length), Some(lines_per_file)) if lines_per_file > 0 => (line_length, lines_per_file),
+                    _ => return Err("filenames need a line_length and lines_per_file".into()),
+                };
+                self.checkSlice(line_length)?;
+                self.checkSheetSize(line_length, lines_per_file)?;
+                let capacity = filenames.len() as i32 * line_length * lines_per_file;
+                if capacity < frames {
+                    return Err(format!(
+                        "{} files of {}x{} frames can't hold {} frames",
+                        filenames.len(), line_length, lines_per_file, frames,
+                    ).into());
+                }
+            }
+            (None, None, Some(stripes)) => {
+                if self.slice.is_some() || self.line_length.is_some() || self.lines_per_file.is_some() {
+                    return Err("stripes take no slice, line_length or lines_per_file".into());
+                }
+                if stripes.iter().any(|stripe| stripe.width_in_frames < 1 || stripe.height_in_frames < 1) {
+                    return Err("stripes need at least one frame".into());
+                }
+                let capacity: i32 = stripes.iter().map(|stripe| stripe.width_in_frames * stripe.height_in_frames).sum();
+                if capacity < frames {
+                    return Err(format!("{} stripes can't hold {} frames", stripes.len(), frames).into());
+                }
+            }
+            _ => return Err("exactly one of filename, filenames and stripes must be set".into()),
+        }
+        Ok(())
+    }
+}
+
+impl DrawFields {
+    fn validate(&self, frame_count: i32) -> CheckResult {
+        if let Some(animation_speed) = self.animation_speed {
+            if !animation_speed.is_finite() || animation_speed <= 0.0 {
+                return Err(format!("animation_speed {} is not positive", animation_speed).into());
+            }
+        }
+        if let Some(frame_sequence) = &self.frame_sequence {
+            if frame_sequence.is_empty() || frame_sequence.len() > MAX_FRAME_SEQUENCE_LENGTH {
+                return Err(format!(
+                    "frame_sequence has {} frames, 1..={} allowed",
+                    frame_sequence.len(), MAX_FRAME_SEQUENCE_LENGTH,
+                ).into());
+            }
+            if let Some(frame) = frame_sequence.iter().find(|&&frame| frame < 1 || frame > frame_count) {
+                return Err(format!("frame_sequence refers to frame {} of {}", frame, frame_count).into());
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Animation {
+    /// Checks the constraints Factorio puts on an animation, including its `hr_version`.
+    pub fn validate(&self) -> CheckResult {
+        let frame_count = self.files.frame_count.unwrap_or(1);
+        if frame_count < 1 {
+            return Err(format!("frame_count {} is not positive", frame_count).into());
+        }
+        self.files.validate(frame_count)?;
+        self.draw.validate(frame_count)?;
+        match &self.hr_version {
+            Some(hr_version) => hr_version.validate().map_err(|err| format!("hr_version: {}", err).into()),
+            None => Ok(()),
+        }
+    }
+}
+
+impl RotatedAnimation {
+    /// Checks the constraints Factorio puts on a rotated animation, including its
+    /// `hr_version`.
+    pub fn validate(&self) -> CheckResult {
+        let frame_count = self.files.frame_count.unwrap_or(1);
+        if frame_count < 1 || self.direction_count < 1 {
+            return Err(format!(
+                "{} frames in {} directions, both must be positive",
+                frame_count, self.direction_count,
+            ).into());
+        }
+        self.files.validate(frame_count * self.direction_count)?;
+        self.draw.validate(frame_count)?;
+        match &self.hr_version {
+            Some(hr_version) => hr_version.validate().map_err(|err| format!("hr_version: {}", err).into()),
+            None => Ok(()),
+        }
+    }
+}
+
+/// A rotated animation of a single direction, as one of a list of variations.
+impl From<RotatedAnimation> for Animation {
+    fn from(rotated: RotatedAnimation) -> Animation {
+        Animation {
+            files: rotated.files,
+            draw: rotated.draw,
+            hr_version: rotated.hr_version.map(|hr_version| Box::new(Animation::from(*hr_version))),
+        }
+    }
+}
+
+impl AnimationVariations {
+    pub fn validate(&self) -> CheckResult {
+        for (i, animation) in self.0.iter().enumerate() {
+            animation.validate().map_err(|err| format!("variation {}: {}", i + 1, err))?;
+        }
+        Ok(())
+    }
+}
+
+impl Sprite {
+    pub fn validate(&self) -> CheckResult {
+        checkSize(self.width, self.height)?;
+        match &self.hr_version {
+            Some(hr_version) => hr_version.validate().map_err(|err| format!("hr_version: {}", err).into()),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Sprite4Way {
+    pub fn directions(&self) -> [(&'static str, &Sprite); 4] {
+        [("north", &self.north), ("east", &self.east), ("south", &self.south), ("west", &self.west)]
+    }
+
+    pub fn validate(&self) -> CheckResult {
+        for (direction, sprite) in self.directions().iter() {
+            sprite.validate().map_err(|err| format!("{}: {}", direction, err))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sheet(line_length: Option<i32>, lines_per_file: Option<i32>) -> SheetFiles {
+        SheetFiles {
+            filename: Some(String::from("sheet.png")),
+            filenames: None,
+            stripes: None,
+            slice: line_length,
+            line_length,
+            lines_per_file,
+            width: 64,
+            height: 64,
+            frame_count: Some(8),
+        }
+    }
+
+    #[test]
+    fn singleFileSheets() {
+        assert!(sheet(Some(4), None).validate(8).is_ok());
+        assert!(sheet(None, None).validate(1).is_ok());
+        assert!(sheet(None, None).validate(8).is_err());
+        assert!(sheet(Some(4), Some(2)).validate(8).is_err());
+        assert!(SheetFiles { slice: Some(2), ..sheet(Some(4), None) }.validate(8).is_err());
+        // 200 frames of 64 pixels are 12800 pixels across
+        assert!(sheet(Some(200), None).validate(200).is_err());
+        assert!(sheet(Some(100), None).validate(200).is_ok());
+    }
+}
@@ -0,0 +1,265 @@
+use std::error::Error;
+use std::fs::read_to_string;
+use std::path::Path;
+use std::slice::Iter;
+use std::str::FromStr;
+use crate::sprite_maker::Preset;
+use crate::lua;
+use crate::prototype::MAX_IMAGE_SIZE;
+
+/// info.json of the mod this tool was written for, used when the output directory has none.
+const BUNDLED_INFO: &str = include_str!("../luasrc/info.json");
+
+/// Game version the Lua is written for.
+#[derive(Copy, Clone, PartialEq)]
+pub enum FactorioVersion {
+    /// SD sheets with the HD ones as `hr_version`
+    v1_1,
+    /// HD sheets only, at half scale
+    v2_0,
+}
+
+impl FromStr for FactorioVersion {
+    type Err = String;
+
+    fn from_str(version: &str) -> Result<FactorioVersion, String> {
+        match version {
+            "1.1" => Ok(FactorioVersion::v1_1),
+            "2.0" => Ok(FactorioVersion::v2_0),
+            _ => Err(format!("unsupported factorio version: {}", version)),
+        }
+    }
+}
+
+/// The fields of an info.json the output depends on. The version is left unparsed, as
+/// `--factorio-version` overrides it.
+struct ModInfo {
+    name: String,
+    factorio_version: String,
+}
+
+fn modInfo(info: &str) -> Result<ModInfo, Box<dyn Error + Send + Sync>> {
+    let info: serde_json::Value = serde_json::from_str(info)?;
+    Ok(ModInfo {
+        name: info["name"].as_str()
+            .map(String::from)
+            .ok_or("info.json has no name")?,
+        factorio_version: info["factorio_version"].as_str()
+            .map(String::from)
+            .ok_or("info.json has no factorio_version")?,
+    })
+}
+
+/// Constraints on the sprite sheets written for one resolution.
+#[derive(Clone)]
+pub struct SheetLimits {
+    pub max_size: i32,
+    pub power_of_two: bool,
+    pub max_files: Option<i32>,
+}
+
+/// Filter used to halve HD frames into SD ones.
+#[derive(Copy, Clone)]
+pub enum SdFilter {
+    /// averages the source texels covered by each output texel
+    boxAverage,
+    triangle,
+    lanczos3,
+    catmullRom,
+}
+
+impl FromStr for SdFilter {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<SdFilter, String> {
+        match name {
+            "box" => Ok(SdFilter::boxAverage),
+            "triangle" => Ok(SdFilter::triangle),
+            "lanczos3" => Ok(SdFilter::lanczos3),
+            "catmull-rom" => Ok(SdFilter::catmullRom),
+            _ => Err(format!("unknown filter: {}", name)),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct SdFilters {
+    pub normal: SdFilter,
+    pub mask: SdFilter,
+    pub light: SdFilter,
+}
+
+impl SdFilters {
+    pub fn forPreset(&self, preset: Preset) -> SdFilter {
+        match preset {
+            Preset::normal => self.normal,
+            Preset::mask => self.mask,
+            Preset::light => self.light,
+        }
+    }
+}
+
+/// Settings of a single extractor run, taken from the command line.
+pub struct RunConfig {
+    pub storage_path: String,
+    pub output_dir: String,
+    pub hd_limits: SheetLimits,
+    pub sd_limits: SheetLimits,
+    pub sd_filters: SdFilters,
+    /// Also write copies of the HD sheets with frame centers, cell borders, radial
+    /// offsets and source frame indices drawn in, under `debug/`.
+    pub debug_overlay: bool,
+    /// Also write unit prototypes built from units.dat to `units.lua`.
+    pub write_units: bool,
+    /// Also write wireframes and command icons as GUI sprites to `sprites.lua`.
+    pub write_gui_sprites: bool,
+    /// Name in the `__mod__/` prefix of sheet paths.
+    pub mod_name: String,
+    /// Where sheets go inside the mod, with `{resolution}`, `{category}` and `{file}`
+    /// replaced.
+    pub path_template: String,
+    pub hd_folder: String,
+    pub sd_folder: String,
+    pub factorio_version: FactorioVersion,
+    /// Layout of the written Lua files.
+    pub lua_style: lua::PrintStyle,
+    /// How the prototype files hand their prototypes to Factorio. `anim.lua` is always a
+    /// module, the other files `require` it.
+    pub prototype_wrapper: lua::Wrapper,
+    /// Re-read `anim.lua` and its sheets after writing and fail on what Factorio would
+    /// refuse to load.
+    pub check_sheets: bool,
+    /// Drop the entries of an existing `anim.lua` this run didn't write, hand written ones
+    /// included, rather than keeping them. Debug builds refuse it once they skip a group.
+    pub prune_entries: bool,
+}
+
+impl Default for RunConfig {
+    fn default() -> RunConfig {
+        let info = modInfo(BUNDLED_INFO).unwrap();
+        RunConfig {
+            storage_path: String::from("/home/henryj/Games/battlenet/drive_c/Program Files (x86)/StarCraft/"),
+            output_dir: String::from("."),
+            hd_limits: SheetLimits {
+                max_size: 4096,
+                power_of_two: false,
+                max_files: None,
+            },
+            sd_limits: SheetLimits {
+                max_size: 2048,
+                power_of_two: false,
+                max_files: None,
+            },
+            sd_filters: SdFilters {
+                normal: SdFilter::lanczos3,
+                mask: SdFilter::lanczos3,
+                // glows are smooth already, lanczos only adds ringing to them
+                light: SdFilter::triangle,
+            },
+            debug_overlay: false,
+            write_units: false,
+            write_gui_sprites: false,
+            mod_name: info.name,
+            path_template: String::from("graphics/{resolution}/{category}/{file}"),
+            hd_folder: String::from("hd"),
+            sd_folder: String::from("sd"),
+            factorio_version: info.factorio_version.parse().unwrap(),
+            lua_style: lua::PRETTY,
+            prototype_wrapper: lua::Wrapper::dataExtend,
+            check_sheets: false,
+            prune_entries: false,
+        }
+    }
+}
+
+fn optionValue<T: FromStr>(args: &mut Iter<String>, option: &str) -> Result<T, Box<dyn Error + Send + Sync>> {
+    let value = args.next()
+        .ok_or_else(|| format!("{} expects a value", option))?;
+    value.parse()
+        .map_err(|_| format!("invalid value for {}: {}", option, value).into())
+}
+
+impl RunConfig {
+    /// Parses `[options] [output_dir]`, without the program name.
+    pub fn fromArgs(args: &[String]) -> Result<RunConfig, Box<dyn Error + Send + Sync>> {
+        let mut config = RunConfig::default();
+        let mut mod_name = None;
+        let mut factorio_version = None;
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            match &arg[..] {
+                "--storage" => config.storage_path = optionValue(&mut args, arg)?,
+                "--max-sheet-size" => config.hd_limits.max_size = optionValue(&mut args, arg)?,
+                "--max-sheet-size-sd" => config.sd_limits.max_size = optionValue(&mut args, arg)?,
+                "--power-of-two" => {
+                    config.hd_limits.power_of_two = true;
+                    config.sd_limits.power_of_two = true;
+                }
+                "--max-files" => {
+                    let max_files = optionValue(&mut args, arg)?;
+                    config.hd_limits.max_files = Some(max_files);
+                    config.sd_limits.max_files = Some(max_files);
+                }
+                "--sd-filter" => {
+                    let filter = optionValue(&mut args, arg)?;
+                    config.sd_filters = SdFilters { normal: filter, mask: filter, light: filter };
+                }
+                "--sd-filter-normal" => config.sd_filters.normal = optionValue(&mut args, arg)?,
+                "--sd-filter-mask" => config.sd_filters.mask = optionValue(&mut args, arg)?,
+                "--sd-filter-light" => config.sd_filters.light = optionValue(&mut args, arg)?,
+                "--debug-overlay" => config.debug_overlay = true,
+                "--units" => config.write_units = true,
+                "--gui-sprites" => config.write_gui_sprites = true,
+                "--mod-name" => mod_name = Some(optionValue(&mut args, arg)?),
+                "--path-template" => config.path_template = optionValue(&mut args, arg)?,
+                "--hd-folder" => config.hd_folder = optionValue(&mut args, arg)?,
+                "--sd-folder" => config.sd_folder = optionValue(&mut args, arg)?,
+                "--factorio-version" => factorio_version = Some(optionValue(&mut args, arg)?),
+                "--minify-lua" => config.lua_style.layout = lua::Layout::minified,
+                "--sort-lua-keys" => config.lua_style.sort_keys = true,
+                "--prototype-wrapper" => config.prototype_wrapper = optionValue(&mut args, arg)?,
+                "--check" => config.check_sheets = true,
+                "--prune" => config.prune_entries = true,
+                _ if arg.starts_with("--") => return Err(format!("unknown option: {}", arg).into()),
+                _ => config.output_dir = arg.clone(),
+            }
+        }
+        for limits in &[&config.hd_limits, &config.sd_limits] {
+            if limits.max_size < 1 || limits.max_files.is_some_and(|max_files| max_files < 1) {
+                return Err("sheet limits must be positive".into());
+            }
+            if limits.max_size > MAX_IMAGE_SIZE {
+                return Err(format!("Factorio loads no sheets over {} pixels across", MAX_IMAGE_SIZE).into());
+            }
+        }
+        if !config.path_template.contains("{file}") {
+            return Err("--path-template needs a {file} placeholder".into());
+        }
+        let info_path = Path::new(&config.output_dir).join("info.json");
+        if info_path.exists() {
+            let info = modInfo(&read_to_string(info_path)?)?;
+            config.mod_name = info.name;
+            if factorio_version.is_none() {
+                match info.factorio_version.parse() {
+                    Ok(version) => config.factorio_version = version,
+                    Err(err) => println!("Warning: info.json: {}, writing for the bundled version", err),
+                }
+            }
+        }
+        if let Some(mod_name) = mod_name {
+            config.mod_name = mod_name;
+        }
+        if let Some(factorio_version) = factorio_version {
+            config.factorio_version = factorio_version;
+        }
+        Ok(config)
+    }
+
+    /// Path of a sheet relative to the mod root.
+    pub fn sheetPath(&self, resolution_folder: &str, category: &str, file: &str) -> String {
+        self.path_template
+            .replace("{resolution}", resolution_folder)
+            .replace("{category}", category)
+            .replace("{file}", file)
+    }
+}
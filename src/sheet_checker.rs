@@ -0,0 +1,227 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs::read_to_string;
+use std::path::Path;
+use crate::lua;
+use crate::lua_parser;
+use crate::prototype::{MAX_SPRITE_SIZE, MAX_IMAGE_SIZE};
+use crate::run_config::RunConfig;
+
+/// Reads the sizes of the sheets a written `anim.lua` refers to, each file once.
+struct SheetChecker<'a> {
+    config: &'a RunConfig,
+    image_sizes: HashMap<String, Result<(u32, u32), String>>,
+    problems: Vec<String>,
+}
+
+fn integerField(field_list: &[(String, lua::Exp)], key: &str) -> Result<Option<i64>, String> {
+    match field_list.iter().find(|(field_key, _)| field_key == key) {
+        None => Ok(None),
+        Some((_, lua::Exp::Integer(value))) => Ok(Some(*value)),
+        Some((_, lua::Exp::Number(value))) if value.get().fract() == 0.0 => Ok(Some(value.get() as i64)),
+        Some(_) => Err(format!("{} is not an integer", key)),
+    }
+}
+
+fn stringExp(exp: &lua::Exp, what: &str) -> Result<String, String> {
+    match exp {
+        lua::Exp::String(value) => Ok(value.clone()),
+        _ => Err(format!("{} is not a string", what)),
+    }
+}
+
+impl<'a> SheetChecker<'a> {
+    /// Size of a sheet given as `__mod__/path`, `None` for files of other mods.
+    fn imageSize(&mut self, filename: &str) -> Option<Result<(u32, u32), String>> {
+        let path = filename.strip_prefix(&format!("__{}__/", self.config.mod_name))?;
+        let output_dir = &self.config.output_dir;
+        let size = self.image_sizes.entry(String::from(path)).or_insert_with(|| {
+            let full_path = Path::new(output_dir).join(path);
+            if !full_path.exists() {
+                return Err(format!("{} doesn't exist", path));
+            }
+            let (width, height) = image::image_dimensions(&full_path)
+                .map_err(|err| format!("{}: {}", path, err))?;
+            if width > MAX_IMAGE_SIZE as u32 || height > MAX_IMAGE_SIZE as u32 {
+                return Err(format!("{} is {}x{}, more than {} pixels across", path, width, height, MAX_IMAGE_SIZE));
+            }
+            Ok((width, height))
+        });
+        Some(size.clone())
+    }
+
+    /// Checks that `file` holds `frames` frames of `width`x`height` lined up `line_length`
+    /// to a row from `(x, y)`, and has room for a row of `slice` frames when one is given.
+    fn checkFile(
+        &mut self,
+        file: &str,
+        (x, y): (i64, i64),
+        (width, height): (i64, i64),
+        line_length: i64,
+        slice: Option<i64>,
+        frames: i64,
+    ) -> Result<(), String> {
+        let (image_width, image_height) = match self.imageSize(file) {
+            Some(size) => size?,
+            None => return Ok(()),
+        };
+        if let Some(slice) = slice {
+            let columns = (image_width as i64 - x) / width;
+            if columns < slice {
+                return Err(format!("{} has {} columns of {} pixels, slice is {}", file, columns, width, slice));
+            }
+        }
+        let columns = line_length.min(frames);
+        let rows = (frames + line_length - 1) / line_length;
+        let (needed_width, needed_height) = (x + width * columns, y + height * rows);
+        if needed_width > image_width as i64 || needed_height > image_height as i64 {
+            return Err(format!(
+                "{} is {}x{}, {} frames of {}x{} need {}x{}",
+                file, image_width, image_height, frames, width, height, needed_width, needed_height,
+            ));
+        }
+        Ok(())
+    }
+
+    /// Checks a table with a `filename`, `filenames` or `stripes` the way Factorio loads it.
+    fn checkSheet(&mut self, field_list: &[(String, lua::Exp)]) -> Result<(), String> {
+        let field = |key: &str| field_list.iter().find(|(field_key, _)| field_key == key).map(|(_, exp)| exp);
+        let width = integerField(field_list, "width")?.ok_or("no width")?;
+        let height = integerField(field_list, "height")?.ok_or("no height")?;
+        if width < 1 || height < 1 || width > MAX_SPRITE_SIZE as i64 || height > MAX_SPRITE_SIZE as i64 {
+            return Err(format!("frames of {}x{} are outside 1..={}", width, height, MAX_SPRITE_SIZE));
+        }
+        let frame_count = integerField(field_list, "frame_count")?.unwrap_or(1);
+        let direction_count = integerField(field_list, "direction_count")?.unwrap_or(1);
+        if frame_count < 1 || direction_count < 1 {
+            return Err(format!("{} frames in {} directions", frame_count, direction_count));
+        }
+        let frames = frame_count * direction_count;
+        let position = (integerField(field_list, "x")?.unwrap_or(0), integerField(field_list, "y")?.unwrap_or(0));
+        let line_length = integerField(field_list, "line_length")?.filter(|&line_length| line_length > 0);
+        let slice = integerField(field_list, "slice")?;
+        match (slice, line_length) {
+            (Some(slice), _) if slice < 1 => return Err(format!("slice {} is not positive", slice)),
+            // frames are read in lines of `slice`, a different line_length misplaces them
+            (Some(slice), Some(line_length)) if slice != line_length => {
+                return Err(format!("slice {} differs from line_length {}", slice, line_length));
+            }
+            _ => {}
+        }
+        if let Some(stripes) = field("stripes") {
+            let stripes = match stripes {
+                lua::Exp::Array { member_list } => member_list,
+                _ => return Err(String::from("stripes is not an array")),
+            };
+            if slice.is_some() {
+                return Err(String::from("stripes take no slice"));
+            }
+            let mut capacity = 0;
+            for stripe in stripes {
+                let stripe = match stripe {
+                    lua::Exp::Table { field_list } => field_list,
+                    _ => return Err(String::from("a stripe is not a table")),
+                };
+                let file = stringExp(
+                    stripe.iter().find(|(key, _)| key == "filename").map(|(_, exp)| exp).ok_or("a stripe has no filename")?,
+                    "filename",
+                )?;
+                let width_in_frames = integerField(stripe, "width_in_frames")?.ok_or("a stripe has no width_in_frames")?;
+                let height_in_frames = integerField(stripe, "height_in_frames")?.ok_or("a stripe has no height_in_frames")?;
+                if width_in_frames < 1 || height_in_frames < 1 {
+                    return Err(format!("{} is a stripe of {}x{} frames", file, width_in_frames, height_in_frames));
+                }
+                self.checkFile(&file, (0, 0), (width, height), width_in_frames, None, width_in_frames * height_in_frames)?;
+                capacity += width_in_frames * height_in_frames;
+            }
+            if capacity < frames {
+                return Err(format!("{} stripes hold {} frames, {} needed", stripes.len(), capacity, frames));
+            }
+        } else if let Some(filenames) = field("filenames") {
+            let filenames = match filenames {
+                lua::Exp::Array { member_list } => member_list,
+                _ => return Err(String::from("filenames is not an array")),
+            };
+            let line_length = line_length.ok_or("filenames without a line_length")?;
+            let lines_per_file = integerField(field_list, "lines_per_file")?
+                .filter(|&lines_per_file| lines_per_file > 0)
+                .ok_or("filenames without lines_per_file")?;
+            let per_file = line_length * lines_per_file;
+            if filenames.len() as i64 * per_file < frames {
+                return Err(format!(
+                    "{} files of {}x{} frames can't hold {} frames",
+                    filenames.len(), line_length, lines_per_file, frames,
+                ));
+            }
+            for (i, file) in filenames.iter().enumerate() {
+                let file = stringExp(file, "a filename")?;
+                let file_frames = (frames - i as i64 * per_file).min(per_file);
+                if file_frames > 0 {
+                    self.checkFile(&file, position, (width, height), line_length, slice, file_frames)?;
+                }
+            }
+        } else if let Some(file) = field("filename") {
+            let file = stringExp(file, "filename")?;
+            let line_length = line_length.or(slice).unwrap_or(frame_count);
+            self.checkFile(&file, position, (width, height), line_length, slice, frames)?;
+        }
+        Ok(())
+    }
+
+    /// Checks every sheet in `exp`, found at `path` of the returned table.
+    fn checkExp(&mut self, exp: &lua::Exp, path: &str) {
+        match exp {
+            lua::Exp::Table { field_list } => {
+                let is_sheet = field_list.iter().any(|(key, _)| ["filename", "filenames", "stripes"].contains(&&key[..]))
+                    && field_list.iter().any(|(key, _)| key == "width");
+                if is_sheet {
+                    if let Err(problem) = self.checkSheet(field_list) {
+                        self.problems.push(format!("{}: {}", path, problem));
+                    }
+                }
+                for (key, exp) in field_list {
+                    if key != "stripes" {
+                        self.checkExp(exp, &format!("{}.{}", path, key));
+                    }
+                }
+            }
+            lua::Exp::Array { member_list } => {
+                for (i, exp) in member_list.iter().enumerate() {
+                    self.checkExp(exp, &format!("{}[{}]", path, i + 1));
+                }
+            }
+            lua::Exp::KeyedTable { field_list } => {
+                for (_, exp) in field_list {
+                    self.checkExp(exp, path);
+                }
+            }
+            lua::Exp::Function { body, .. } => {
+                if let Some(lua::LastStat::Return { exp_list }) = &body.last_stat {
+                    for exp in exp_list {
+                        self.checkExp(exp, path);
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Re-reads the written `anim.lua` and the sheets it refers to, and returns what Factorio
+/// would refuse to load: missing files, sheets too small for the frames they should hold
+/// and sizes over its limits. Sheets of other mods are not looked at.
+pub fn checkSheets(config: &RunConfig) -> Result<Vec<String>, Box<dyn Error + Send + Sync>> {
+    let path = format!("{}/anim.lua", config.output_dir);
+    let block = lua_parser::parse(&read_to_string(&path)?)
+        .map_err(|err| format!("{}: {}", path, err))?;
+    let mut checker = SheetChecker { config, image_sizes: HashMap::new(), problems: Vec::new() };
+    match &block.last_stat {
+        Some(lua::LastStat::Return { exp_list }) => {
+            for exp in exp_list {
+                checker.checkExp(exp, "anim");
+            }
+        }
+        _ => return Err(format!("{}: doesn't return a table of animations", path).into()),
+    }
+    Ok(checker.problems)
+}
@@ -0,0 +1,332 @@
+use std::error::Error;
+use crate::run_config::SheetLimits;
+use crate::sprite_maker::Stripe;
+
+pub struct SheetImage {
+    pub columns: i32,
+    pub rows: i32,
+    pub frames: i32,
+    pub width: i32,
+    pub height: i32,
+}
+
+/// Placement of equally sized frame cells over one or more sheet images.
+pub struct SheetLayout {
+    pub cell_width: i32,
+    pub cell_height: i32,
+    /// Position of the frame anchor inside a cell, in half pixels.
+    pub anchor_x2: (i32, i32),
+    pub images: Vec<SheetImage>,
+    /// Whether every image is its own stripe rather than a file of a uniform grid.
+    pub stripes: bool,
+}
+
+impl SheetLayout {
+    pub fn cellPosition(&self, idx: i32) -> (usize, i32, i32) {
+        let mut first = 0;
+        for (img_idx, image) in self.images.iter().enumerate() {
+            if idx < first + image.frames {
+                let cell_idx = idx - first;
+                return (
+                    img_idx,
+                    cell_idx % image.columns * self.cell_width,
+                    cell_idx / image.columns * self.cell_height,
+                );
+            }
+            first += image.frames;
+        }
+        panic!("frame index {} is outside of the sheet layout", idx)
+    }
+
+    pub fn byteSize(&self) -> u64 {
+        self.images.iter()
+            .map(|image| image.width as u64 * image.height as u64 * 4)
+            .sum()
+    }
+
+    fn unusedArea(&self, frame_count: i32) -> i64 {
+        self.byteSize() as i64 / 4 - frame_count as i64 * self.cell_width as i64 * self.cell_height as i64
+    }
+
+    /// How far the first sheet is from being square, the final tie breaker between layouts.
+    fn squareness(&self) -> i32 {
+        self.images.first()
+            .map_or(0, |image| (image.width - image.height).abs())
+    }
+}
+
+fn sheetSize(pixels: i32, limits: &SheetLimits) -> i32 {
+    if limits.power_of_two {
+        (pixels as u32).next_power_of_two() as i32
+    } else {
+        pixels
+    }
+}
+
+/// The most cells of `cell_size` pixels that fit along one side of a sheet, an error
+/// when not even one does.
+fn maxCells(cell_size: i32, limits: &SheetLimits) -> Result<i32, Box<dyn Error + Send + Sync>> {
+    if cell_size < 1 {
+        return Err(format!("cells of {} pixels can't be laid out", cell_size).into());
+    }
+    if sheetSize(cell_size, limits) > limits.max_size {
+        return Err(format!(
+            "a cell of {} pixels doesn't fit on a sheet of at most {}",
+            cell_size, limits.max_size,
+        ).into());
+    }
+    let mut count = limits.max_size / cell_size;
+    while count > 1 && sheetSize(count * cell_size, limits) > limits.max_size {
+        count -= 1;
+    }
+    Ok(count)
+}
+
+fn sheetImage(columns: i32, rows: i32, frames: i32, cell_width: i32, cell_height: i32, limits: &SheetLimits) -> SheetImage {
+    SheetImage {
+        columns,
+        rows,
+        frames,
+        width: sheetSize(columns * cell_width, limits),
+        height: sheetSize(rows * cell_height, limits),
+    }
+}
+
+/// Lays the frames out as uniform files of `line_length × lines_per_file` cells, picking
+/// the shape that needs the fewest files and then wastes the least area. With
+/// `split_length` every file holds exactly that many frames.
+pub fn gridLayout(
+    cell_width: i32,
+    cell_height: i32,
+    frame_count: i32,
+    split_length: Option<i32>,
+    limits: &SheetLimits,
+) -> Result<SheetLayout, Box<dyn Error + Send + Sync>> {
+    let max_columns = maxCells(cell_width, limits)?;
+    let max_rows = maxCells(cell_height, limits)?;
+    let frames_per_file = split_length.unwrap_or(frame_count).max(1);
+
+    let mut best: Option<(SheetLayout, bool)> = None;
+    for columns in 1..=max_columns.min(frames_per_file) {
+        let rows = match split_length {
+            Some(length) => (length + columns - 1) / columns,
+            None => {
+                let rows = max_rows.min((frame_count + columns - 1) / columns);
+                let file_count = (frame_count + columns * rows - 1) / (columns * rows);
+                (frame_count + file_count * columns - 1) / (file_count * columns)
+            }
+        };
+        // split files may not fit at all, any layout that does is preferred
+        let overflow = rows > max_rows;
+        let frames = split_length.unwrap_or(columns * rows);
+        let file_count = (frame_count + frames - 1) / frames;
+        let layout = SheetLayout {
+            cell_width,
+            cell_height,
+            anchor_x2: (cell_width, cell_height),
+            images: (0..file_count)
+                .map(|_| sheetImage(columns, rows, frames, cell_width, cell_height, limits))
+                .collect(),
+            stripes: false,
+        };
+        let better = match &best {
+            Some((best, best_overflow)) => {
+                (overflow, layout.images.len(), layout.unusedArea(frame_count), layout.squareness())
+                    < (*best_overflow, best.images.len(), best.unusedArea(frame_count), best.squareness())
+            }
+            None => true,
+        };
+        if better { best = Some((layout, overflow)); }
+    }
+    match best.unwrap() {
+        (_, true) => Err(format!(
+            "{} frames of {}x{} don't fit on a sheet of at most {}",
+            frames_per_file, cell_width, cell_height, limits.max_size,
+        ).into()),
+        (layout, false) => Ok(layout),
+    }
+}
+
+/// Lays trimmed cells out as exact stripes, using as few files and as little area as
+/// possible. `extents_x2` holds the reach of the content from the anchor as
+/// (left, top, right, bottom) in half pixels. Every stripe of an animation has the same
+/// cell size, so the cells are trimmed to the extents of all frames together.
+pub fn tightLayout(
+    extents_x2: (i32, i32, i32, i32),
+    frame_count: i32,
+    limits: &SheetLimits,
+) -> Result<SheetLayout, Box<dyn Error + Send + Sync>> {
+    let (left, top, right, bottom) = extents_x2;
+    let mut cell_width = (left + right + 1) / 2;
+    let mut cell_height = (top + bottom + 1) / 2;
+    // keep cells even so the SD sheets halve exactly
+    cell_width += cell_width % 2;
+    cell_height += cell_height % 2;
+    stripesLayout(cell_width, cell_height, (left, top), frame_count, limits)
+}
+
+fn stripesLayout(
+    cell_width: i32,
+    cell_height: i32,
+    anchor_x2: (i32, i32),
+    frame_count: i32,
+    limits: &SheetLimits,
+) -> Result<SheetLayout, Box<dyn Error + Send + Sync>> {
+    let max_columns = maxCells(cell_width, limits)?;
+    let max_rows = maxCells(cell_height, limits)?;
+
+    let mut best: Option<SheetLayout> = None;
+    for columns in 1..=max_columns.min(frame_count.max(1)) {
+        let mut images = Vec::new();
+        let mut rows_left = frame_count / columns;
+        while rows_left > 0 {
+            let rows = rows_left.min(max_rows);
+            images.push(sheetImage(columns, rows, columns * rows, cell_width, cell_height, limits));
+            rows_left -= rows;
+        }
+        let remainder = frame_count % columns;
+        if remainder > 0 {
+            images.push(sheetImage(remainder, 1, remainder, cell_width, cell_height, limits));
+        }
+        let layout = SheetLayout {
+            cell_width,
+            cell_height,
+            anchor_x2,
+            images,
+            stripes: true,
+        };
+        let better = match &best {
+            Some(best) => {
+                (layout.images.len(), layout.unusedArea(frame_count), layout.squareness())
+                    < (best.images.len(), best.unusedArea(frame_count), best.squareness())
+            }
+            None => true,
+        };
+        if better { best = Some(layout); }
+    }
+    Ok(best.unwrap())
+}
+
+/// Repeats the stripes of another layout with cells of a different size, as the SD sheets
+/// do. Stripes that would grow past `limits` are laid out anew instead.
+pub fn stripeLayout(
+    stripes: &[Stripe],
+    cell_width: i32,
+    cell_height: i32,
+    limits: &SheetLimits,
+) -> Result<SheetLayout, Box<dyn Error + Send + Sync>> {
+    let images: Vec<SheetImage> = stripes.iter()
+        .map(|stripe| sheetImage(
+            stripe.width_in_frames,
+            stripe.height_in_frames,
+            stripe.width_in_frames * stripe.height_in_frames,
+            cell_width,
+            cell_height,
+            limits,
+        ))
+        .collect();
+    if images.iter().any(|image| image.width > limits.max_size || image.height > limits.max_size) {
+        let frame_count = images.iter().map(|image| image.frames).sum();
+        return stripesLayout(cell_width, cell_height, (cell_width, cell_height), frame_count, limits);
+    }
+    Ok(SheetLayout {
+        cell_width,
+        cell_height,
+        anchor_x2: (cell_width, cell_height),
+        images,
+        stripes: true,
+    })
+}
+
+/// Placement of frames of different sizes over one or more sheet images, each frame
+/// written as its own sprite.
+pub struct AtlasLayout {
+    /// Image index and top left pixel of every frame, in frame order.
+    pub placements: Vec<(usize, i32, i32)>,
+    /// Width and height of every image.
+    pub images: Vec<(i32, i32)>,
+}
+
+impl AtlasLayout {
+    pub fn byteSize(&self) -> u64 {
+        self.images.iter()
+            .map(|(width, height)| *width as u64 * *height as u64 * 4)
+            .sum()
+    }
+}
+
+/// Packs frames of the given sizes in shelves, tallest first, starting a new image when
+/// one is full. The images are about as wide as a square holding every frame would be.
+/// `None` when a frame doesn't fit on a sheet at all.
+pub fn atlasLayout(sizes: &[(i32, i32)], limits: &SheetLimits) -> Option<AtlasLayout> {
+    let max_width = sizes.iter().map(|size| size.0).max()?;
+    if sizes.iter().any(|&(width, height)| {
+        width < 1 || height < 1 || sheetSize(width, limits) > limits.max_size || sheetSize(height, limits) > limits.max_size
+    }) {
+        return None;
+    }
+    let area: i64 = sizes.iter().map(|&(width, height)| width as i64 * height as i64).sum();
+    let mut sheet_width = max_width.max((area as f64).sqrt().ceil() as i32).min(limits.max_size);
+    if limits.power_of_two {
+        sheet_width = sheetSize(sheet_width, limits);
+        while sheet_width > limits.max_size { sheet_width /= 2; }
+    }
+
+    let mut order: Vec<usize> = (0..sizes.len()).collect();
+    order.sort_by_key(|&idx| (-sizes[idx].1, -sizes[idx].0));
+    let mut placements = vec![(0, 0, 0); sizes.len()];
+    let mut images = Vec::new();
+    // right end of the current shelf, its top and height, and the used width of the image
+    let (mut x, mut shelf_y, mut shelf_height, mut used_width) = (0, 0, 0, 0);
+    for idx in order {
+        let (width, height) = sizes[idx];
+        if x + width > sheet_width {
+            shelf_y += shelf_height;
+            x = 0;
+            shelf_height = 0;
+        }
+        if sheetSize(shelf_y + height, limits) > limits.max_size {
+            images.push((sheetSize(used_width, limits), sheetSize(shelf_y, limits)));
+            shelf_y = 0;
+            used_width = 0;
+        }
+        placements[idx] = (images.len(), x, shelf_y);
+        x += width;
+        used_width = used_width.max(x);
+        shelf_height = shelf_height.max(height);
+    }
+    images.push((sheetSize(used_width, limits), sheetSize(shelf_y + shelf_height, limits)));
+    Some(AtlasLayout { placements, images })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LIMITS: SheetLimits = SheetLimits { max_size: 256, power_of_two: false, max_files: None };
+
+    #[test]
+    fn splitFilesHoldTheirFrames() {
+        let layout = gridLayout(64, 64, 32, Some(8), &LIMITS).unwrap();
+        assert_eq!(layout.images.len(), 4);
+        for image in &layout.images {
+            assert_eq!(image.frames, 8);
+            assert!(image.columns * image.rows >= 8);
+            assert!(image.width <= LIMITS.max_size && image.height <= LIMITS.max_size);
+        }
+    }
+
+    #[test]
+    fn rejectsASplitLengthThatDoesNotFit() {
+        // at most 4x4 cells of 64 pixels fit, 17 frames need a fifth row
+        let err = gridLayout(64, 64, 34, Some(17), &LIMITS).err().unwrap().to_string();
+        assert_eq!(err, "17 frames of 64x64 don't fit on a sheet of at most 256");
+        assert!(gridLayout(64, 64, 32, Some(16), &LIMITS).is_ok());
+    }
+
+    #[test]
+    fn rejectsCellsOverTheLimit() {
+        assert!(gridLayout(257, 64, 1, None, &LIMITS).is_err());
+        assert!(gridLayout(0, 64, 1, None, &LIMITS).is_err());
+    }
+}
@@ -1,9 +1,128 @@
+use crate::direction::{Orientation, STARCRAFT_ORIENTATION, FACTORIO_ORIENTATION};
+
+/// How the frames of a format are laid out on the sprite sheets.
+///
+/// `grid` gives every frame a cell sized after the largest frame extent around the
+/// center and fills fixed-size sheets. `tight` trims empty borders and takes the
+/// smallest of the grid, exact `stripes` with the anchor placed off-center where that
+/// helps, and, for targets that write a sprite per frame, every frame trimmed on its
+/// own and packed onto an atlas. `split_anim` always keeps the grid.
+#[derive(Copy, Clone, PartialEq)]
+pub enum Packing {
+    grid,
+    tight,
+}
+
+/// Animation set of a turret prototype.
+#[derive(Copy, Clone, PartialEq)]
+pub enum TurretState {
+    folded,
+    preparing,
+    prepared,
+    attacking,
+    folding,
+}
+
+impl TurretState {
+    pub const ALL: [TurretState; 5] = [
+        TurretState::folded,
+        TurretState::preparing,
+        TurretState::prepared,
+        TurretState::attacking,
+        TurretState::folding,
+    ];
+
+    /// Field of the turret prototype the animation goes into.
+    pub fn key(&self) -> &'static str {
+        match self {
+            TurretState::folded => "folded_animation",
+            TurretState::preparing => "preparing_animation",
+            TurretState::prepared => "prepared_animation",
+            TurretState::attacking => "attacking_animation",
+            TurretState::folding => "folding_animation",
+        }
+    }
+}
+
+/// Prototype type a format is written as.
+///
+/// `rotatedSprite` drops the animation fields and needs a single frame per direction.
+/// `sprite4Way` writes the single frame of each of its 4 directions as `north`, `east`,
+/// `south` and `west` sprites. `turret` writes a `RotatedAnimation` and also puts it,
+/// with the other turret formats of the category, into the `{category}_turret` set,
+/// one layer per format and preset.
+#[derive(Copy, Clone, PartialEq)]
+pub enum OutputTarget {
+    rotatedAnimation,
+    rotatedSprite,
+    sprite4Way,
+    turret(TurretState),
+}
+
+impl OutputTarget {
+    /// Whether the prototype takes `stripes`, which tight packing writes.
+    pub fn supportsStripes(&self) -> bool {
+        !matches!(self, OutputTarget::rotatedSprite)
+    }
+
+    /// Whether every frame is written as a sprite of its own, with its own size and shift.
+    pub fn writesFrameSprites(&self) -> bool {
+        *self == OutputTarget::sprite4Way
+    }
+}
+
+/// Game frames per second at the fastest game speed.
+pub const GAME_FRAMES_PER_SECOND: f64 = 23.81;
+pub const TICKS_PER_SECOND: f64 = 60.0;
+
+/// How long each frame of a format is shown.
+#[derive(Clone)]
+pub enum FrameTiming {
+    /// 0.4 frames per tick, close to one game frame per frame, for still formats
+    standard,
+    /// game frames per frame
+    gameFrames(f64),
+    /// the `wait` operands of the iscript animation, in game frames, averaged as every
+    /// frame plays for the same time in Factorio
+    iscriptWaits(Vec<i32>),
+}
+
+impl FrameTiming {
+    /// Factorio `animation_speed`, in frames per tick.
+    pub fn animationSpeed(&self) -> f64 {
+        let game_frames = match self {
+            FrameTiming::standard => return 0.4,
+            FrameTiming::gameFrames(game_frames) => *game_frames,
+            FrameTiming::iscriptWaits(waits) => waits.iter().sum::<i32>() as f64 / waits.len() as f64,
+        };
+        GAME_FRAMES_PER_SECOND / TICKS_PER_SECOND / game_frames
+    }
+
+    pub fn isValid(&self) -> bool {
+        match self {
+            FrameTiming::standard => true,
+            FrameTiming::gameFrames(game_frames) => *game_frames > 0.0,
+            FrameTiming::iscriptWaits(waits) => !waits.is_empty() && waits.iter().all(|wait| *wait > 0),
+        }
+    }
+}
+
+/// Decay frames from another anim, played after the frames of a death animation and
+/// held like `corpse_frame_sequence` in util.lua. A format with one becomes a corpse.
+#[derive(Clone)]
+pub struct CorpseSource {
+    pub source: String,
+    pub base_offset_x2: (i32, i32),
+    pub source_range_index: (i32, i32),
+    pub animation_length: i32,
+}
+
 #[derive(Clone)]
 pub struct SpriteFormat {
     pub name: String,
     pub extra_offset_x2: (i32, i32),
     pub radial_offset_x2: (f32, f32),
-    pub final_offset: (f32, f32),
+    pub final_offset: (f64, f64),
     pub direction_count: i32,
     pub used_directions: Option<Vec<i32>>,
     pub animation_length: i32,
@@ -14,7 +133,54 @@ pub struct SpriteFormat {
     pub scalable: bool,
     pub run_mode: String,
     pub frame_sequence: Option<Vec<i32>>,
+    pub timing: FrameTiming,
     pub split_anim: bool,
+    pub packing: Packing,
+    /// Turn the nearest source facing the rest of the way to each direction, for
+    /// direction counts finer than the source.
+    pub rotate_directions: bool,
+    /// Arrangement of the source frames of one animation step.
+    pub source_orientation: Orientation,
+    /// Arrangement of the exported directions.
+    pub orientation: Orientation,
+    pub target: OutputTarget,
+    pub corpse: Option<CorpseSource>,
+}
+
+impl SpriteFormat {
+    /// Frames on the sheets per direction, the decay frames of a corpse included.
+    pub fn framesPerDirection(&self) -> i32 {
+        self.animation_length + self.corpse.as_ref().map_or(0, |corpse| corpse.animation_length)
+    }
+}
+
+impl Default for SpriteFormat {
+    fn default() -> SpriteFormat {
+        SpriteFormat {
+            name: String::new(),
+            extra_offset_x2: (0, 0),
+            radial_offset_x2: (0.0, 0.0),
+            final_offset: (0.0, 0.0),
+            direction_count: 0,
+            used_directions: None,
+            animation_length: 0,
+            empty_pad: 0,
+            source_range_index: (0, 0),
+            draw_as_shadow: false,
+            draw_as_glow: false,
+            scalable: false,
+            run_mode: String::from("forward"),
+            frame_sequence: None,
+            timing: FrameTiming::standard,
+            split_anim: false,
+            packing: Packing::grid,
+            rotate_directions: false,
+            source_orientation: STARCRAFT_ORIENTATION,
+            orientation: FACTORIO_ORIENTATION,
+            target: OutputTarget::rotatedAnimation,
+            corpse: None,
+        }
+    }
 }
 
 pub struct SpriteGroup {
@@ -24,24 +190,199 @@ pub struct SpriteGroup {
     pub sprites: Vec<SpriteFormat>,
 }
 
-pub fn getConfig() -> Vec<SpriteGroup> {
-    let DEFAULT_SPRITE_FORMAT = SpriteFormat {
-        name: String::new(),
-        extra_offset_x2: (0, 0),
-        radial_offset_x2: (0.0, 0.0),
-        final_offset: (0.0, 0.0),
-        direction_count: 0,
-        used_directions: None,
-        animation_length: 0,
-        empty_pad: 0,
-        source_range_index: (0, 0),
-        draw_as_shadow: false,
-        draw_as_glow: false,
-        scalable: false,
-        run_mode: String::from("forward"),
-        frame_sequence: None,
-        split_anim: false
+/// Part a layer plays in a composed animation, which also picks the preset it is
+/// taken from.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum LayerRole {
+    body,
+    /// team colour mask of a body format
+    mask,
+    light,
+    shadow,
+    /// a second body format drawn over the first, like the carrier exhaust
+    overlay,
+}
+
+pub struct ComposedLayer {
+    pub role: LayerRole,
+    pub category: String,
+    pub format: String,
+}
+
+/// One `layers` animation stitched from formats of possibly several sprite groups,
+/// drawn in the order given.
+pub struct Composition {
+    pub name: String,
+    pub layers: Vec<ComposedLayer>,
+}
+
+pub fn getCompositions() -> Vec<Composition> {
+    let layer = |role, category: &str, format: &str| ComposedLayer {
+        role,
+        category: String::from(category),
+        format: String::from(format),
+    };
+    vec![
+        Composition {
+            name: String::from("carrier_run"),
+            layers: vec![
+                layer(LayerRole::body, "carrier", "run"),
+                layer(LayerRole::mask, "carrier", "run"),
+                layer(LayerRole::overlay, "carrier", "exhaust"),
+                layer(LayerRole::shadow, "carrier", "run-shadow"),
+            ],
+        },
+        Composition {
+            name: String::from("dragoon_idle"),
+            layers: vec![
+                layer(LayerRole::body, "dragoon", "idle"),
+                layer(LayerRole::mask, "dragoon", "idle"),
+                layer(LayerRole::shadow, "dragoon", "idle-shadow"),
+            ],
+        },
+        Composition {
+            name: String::from("dragoon_run"),
+            layers: vec![
+                layer(LayerRole::body, "dragoon", "run"),
+                layer(LayerRole::mask, "dragoon", "run"),
+                layer(LayerRole::shadow, "dragoon", "run-shadow"),
+            ],
+        },
+        Composition {
+            name: String::from("dragoon_attack"),
+            layers: vec![
+                layer(LayerRole::body, "dragoon", "attack"),
+                layer(LayerRole::mask, "dragoon", "attack"),
+                layer(LayerRole::shadow, "dragoon", "attack-shadow"),
+            ],
+        },
+        Composition {
+            name: String::from("zealot_run"),
+            layers: vec![
+                layer(LayerRole::body, "zealot", "run"),
+                layer(LayerRole::mask, "zealot", "run"),
+                layer(LayerRole::shadow, "zealot", "run-shadow"),
+            ],
+        },
+        Composition {
+            name: String::from("zealot_attack"),
+            layers: vec![
+                layer(LayerRole::body, "zealot", "attack"),
+                layer(LayerRole::mask, "zealot", "attack"),
+                layer(LayerRole::shadow, "zealot", "attack-shadow"),
+            ],
+        },
+    ]
+}
+
+/// A unit prototype written to `units.lua`. Animations are format names of `category`,
+/// taken from `layered` when a composition of the same name exists. A `die` format with
+/// decay frames is the corpse itself, `corpse` is only for a separate decay format.
+pub struct UnitConfig {
+    pub name: String,
+    /// Index into units.dat.
+    pub unit_id: usize,
+    pub category: String,
+    pub run: String,
+    pub attack: String,
+    pub die: Option<String>,
+    pub corpse: Option<String>,
+    /// Team colour for the mask layers, 0-255.
+    pub tint: (u8, u8, u8),
+}
+
+pub fn getUnits() -> Vec<UnitConfig> {
+    vec![
+        UnitConfig {
+            name: String::from("sc-zealot"),
+            unit_id: 65,
+            category: String::from("zealot"),
+            run: String::from("run"),
+            attack: String::from("attack"),
+            die: Some(String::from("die")),
+            corpse: None,
+            tint: (244, 4, 4),
+        },
+        UnitConfig {
+            name: String::from("sc-dragoon"),
+            unit_id: 66,
+            category: String::from("dragoon"),
+            run: String::from("run"),
+            attack: String::from("attack"),
+            die: Some(String::from("die")),
+            corpse: None,
+            tint: (244, 4, 4),
+        },
+        UnitConfig {
+            name: String::from("sc-archon"),
+            unit_id: 68,
+            category: String::from("archon"),
+            run: String::from("run"),
+            attack: String::from("attack"),
+            die: None,
+            corpse: None,
+            tint: (244, 4, 4),
+        },
+    ]
+}
+
+/// An icon drawn from a single anim frame. `direction` counts source facings clockwise
+/// from north, 17 and up being the mirrored west side, `frame` is the animation step
+/// after `first_frame`.
+pub struct IconConfig {
+    pub name: String,
+    pub category: String,
+    pub source: String,
+    pub first_frame: i32,
+    pub frame: i32,
+    pub direction: i32,
+    /// Team colour multiplied into the mask areas, 0-255.
+    pub tint: Option<(u8, u8, u8)>,
+}
+
+pub fn getIcons() -> Vec<IconConfig> {
+    let icon = |name: &str, source: &str, first_frame| IconConfig {
+        name: String::from(name),
+        category: String::from(name),
+        source: String::from(source),
+        first_frame,
+        frame: 0,
+        // facing south east
+        direction: 12,
+        tint: Some((244, 4, 4)),
+    };
+    vec![
+        icon("carrier", "anim/main_112.anim", 0),
+        icon("dragoon", "anim/main_122.anim", 0),
+        icon("archon", "anim/main_135.anim", 170),
+        icon("zealot", "anim/main_151.anim", 85),
+    ]
+}
+
+/// A frame of a `.dds.grp`, written as a `sprite` prototype on the GUI sprite sheet.
+pub struct GuiSpriteConfig {
+    pub name: String,
+    pub source: String,
+    pub frame: usize,
+}
+
+pub fn getGuiSprites() -> Vec<GuiSpriteConfig> {
+    // wireframes and command icons are both indexed by unit id
+    let sprite = |name: &str, source: &str, frame| GuiSpriteConfig {
+        name: String::from(name),
+        source: String::from(source),
+        frame,
     };
+    let mut sprites = Vec::new();
+    for (name, unit_id) in &[("carrier", 72), ("dragoon", 66), ("archon", 68), ("zealot", 65)] {
+        sprites.push(sprite(&format!("sc-{}-wireframe", name), "unit/wirefram/wirefram.dds.grp", *unit_id));
+        sprites.push(sprite(&format!("sc-{}-command-icon", name), "unit/cmdbtns/cmdicons.dds.grp", *unit_id));
+    }
+    sprites
+}
+
+pub fn getConfig() -> Vec<SpriteGroup> {
+    let DEFAULT_SPRITE_FORMAT = SpriteFormat::default();
     vec![
         SpriteGroup {
             source: String::from("anim/main_112.anim"),
@@ -53,6 +394,7 @@ pub fn getConfig() -> Vec<SpriteGroup> {
                     direction_count: 32,
                     animation_length: 1,
                     source_range_index: (0, 17),
+                    packing: Packing::tight,
                     ..DEFAULT_SPRITE_FORMAT.clone()
                 },
             ],
@@ -68,6 +410,7 @@ pub fn getConfig() -> Vec<SpriteGroup> {
                     animation_length: 1,
                     source_range_index: (0, 17),
                     draw_as_shadow: true,
+                    packing: Packing::tight,
                     ..DEFAULT_SPRITE_FORMAT.clone()
                 },
             ],
@@ -82,6 +425,7 @@ pub fn getConfig() -> Vec<SpriteGroup> {
                     direction_count: 32,
                     animation_length: 1,
                     source_range_index: (0, 17),
+                    packing: Packing::tight,
                     ..DEFAULT_SPRITE_FORMAT.clone()
                 },
             ],
@@ -117,6 +461,8 @@ pub fn getConfig() -> Vec<SpriteGroup> {
                     direction_count: 1,
                     animation_length: 8,
                     source_range_index: (0, 136),
+                    // DragoonGndAttkToIdle
+                    timing: FrameTiming::iscriptWaits(vec![2; 8]),
                     ..DEFAULT_SPRITE_FORMAT.clone()
                 },
                 SpriteFormat {
@@ -124,6 +470,8 @@ pub fn getConfig() -> Vec<SpriteGroup> {
                     direction_count: 4,
                     animation_length: 8,
                     source_range_index: (136, 272),
+                    // DragoonWalking
+                    timing: FrameTiming::iscriptWaits(vec![1; 8]),
                     ..DEFAULT_SPRITE_FORMAT.clone()
                 },
                 SpriteFormat {
@@ -131,6 +479,8 @@ pub fn getConfig() -> Vec<SpriteGroup> {
                     direction_count: 1,
                     animation_length: 8,
                     source_range_index: (272, 408),
+                    // DragoonGndAttkInit
+                    timing: FrameTiming::iscriptWaits(vec![2; 8]),
                     ..DEFAULT_SPRITE_FORMAT.clone()
                 },
                 SpriteFormat {
@@ -138,6 +488,14 @@ pub fn getConfig() -> Vec<SpriteGroup> {
                     direction_count: 1,
                     animation_length: 7,
                     source_range_index: (408, 415),
+                    corpse: Some(CorpseSource {
+                        source: String::from("anim/main_124.anim"),
+                        base_offset_x2: (-8, 0),
+                        source_range_index: (0, 5),
+                        animation_length: 5,
+                    }),
+                    // DragoonDeath, the decay frames are held to the same pace
+                    timing: FrameTiming::iscriptWaits(vec![3; 7]),
                     ..DEFAULT_SPRITE_FORMAT.clone()
                 },
             ],
@@ -153,6 +511,7 @@ pub fn getConfig() -> Vec<SpriteGroup> {
                     animation_length: 8,
                     source_range_index: (0, 136),
                     draw_as_shadow: true,
+                    timing: FrameTiming::iscriptWaits(vec![2; 8]),
                     ..DEFAULT_SPRITE_FORMAT.clone()
                 },
                 SpriteFormat {
@@ -161,6 +520,7 @@ pub fn getConfig() -> Vec<SpriteGroup> {
                     animation_length: 8,
                     source_range_index: (136, 272),
                     draw_as_shadow: true,
+                    timing: FrameTiming::iscriptWaits(vec![1; 8]),
                     ..DEFAULT_SPRITE_FORMAT.clone()
                 },
                 SpriteFormat {
@@ -169,10 +529,13 @@ pub fn getConfig() -> Vec<SpriteGroup> {
                     animation_length: 8,
                     source_range_index: (272, 408),
                     draw_as_shadow: true,
+                    timing: FrameTiming::iscriptWaits(vec![2; 8]),
                     ..DEFAULT_SPRITE_FORMAT.clone()
                 },
             ],
         },
+        // the decay frames on their own, as `anim.dragoon_corpse` was before the death
+        // animation took them in
         SpriteGroup {
             source: String::from("anim/main_124.anim"),
             category: String::from("dragoon"),
@@ -184,6 +547,8 @@ pub fn getConfig() -> Vec<SpriteGroup> {
                     animation_length: 5,
                     source_range_index: (0, 5),
                     empty_pad: 1,
+                    // DragoonDeath, the pace the joined corpse plays these frames at
+                    timing: FrameTiming::iscriptWaits(vec![3; 5]),
                     ..DEFAULT_SPRITE_FORMAT.clone()
                 },
             ],
@@ -200,6 +565,8 @@ pub fn getConfig() -> Vec<SpriteGroup> {
                     source_range_index: (0, 10),
                     draw_as_glow: true,
                     frame_sequence: Option::from(vec![1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 9, 8, 7, 6, 5, 4, 3, 2]),
+                    // ArchonEnergyInit
+                    timing: FrameTiming::iscriptWaits(vec![1; 18]),
                     ..DEFAULT_SPRITE_FORMAT.clone()
                 },
             ],
@@ -216,6 +583,8 @@ pub fn getConfig() -> Vec<SpriteGroup> {
                     source_range_index: (0, 170),
                     radial_offset_x2: (30.0, -20.0),
                     frame_sequence: Option::from(vec![2, 3, 4, 5, 6, 7, 7, 7, 8, 9, 10, 3, 3, 2, 2, 1, 1]),
+                    // ArchonGndAttkInit, the held frames are repeated in the sequence
+                    timing: FrameTiming::iscriptWaits(vec![1; 17]),
                     ..DEFAULT_SPRITE_FORMAT.clone()
                 },
                 SpriteFormat {
@@ -225,6 +594,8 @@ pub fn getConfig() -> Vec<SpriteGroup> {
                     source_range_index: (170, 238),
                     radial_offset_x2: (30.0, -20.0),
                     run_mode: String::from("forward-then-backward"),
+                    // ArchonWalking
+                    timing: FrameTiming::iscriptWaits(vec![1; 4]),
                     ..DEFAULT_SPRITE_FORMAT.clone()
                 },
             ],
@@ -240,6 +611,8 @@ pub fn getConfig() -> Vec<SpriteGroup> {
                     animation_length: 15,
                     source_range_index: (0, 15),
                     draw_as_glow: true,
+                    // ArchonBeingInit
+                    timing: FrameTiming::iscriptWaits(vec![1; 15]),
                     ..DEFAULT_SPRITE_FORMAT.clone()
                 },
             ],
@@ -254,6 +627,8 @@ pub fn getConfig() -> Vec<SpriteGroup> {
                     direction_count: 32,
                     animation_length: 5,
                     source_range_index: (0, 85),
+                    // ZealotGndAttkInit
+                    timing: FrameTiming::iscriptWaits(vec![1; 5]),
                     ..DEFAULT_SPRITE_FORMAT.clone()
                 },
                 SpriteFormat {
@@ -261,6 +636,8 @@ pub fn getConfig() -> Vec<SpriteGroup> {
                     direction_count: 32,
                     animation_length: 8,
                     source_range_index: (85, 221),
+                    // ZealotWalking
+                    timing: FrameTiming::iscriptWaits(vec![1; 8]),
                     ..DEFAULT_SPRITE_FORMAT.clone()
                 },
                 SpriteFormat {
@@ -268,6 +645,8 @@ pub fn getConfig() -> Vec<SpriteGroup> {
                     direction_count: 1,
                     animation_length: 7,
                     source_range_index: (221, 228),
+                    // ZealotDeath
+                    timing: FrameTiming::iscriptWaits(vec![2; 7]),
                     ..DEFAULT_SPRITE_FORMAT.clone()
                 },
             ],
@@ -283,6 +662,7 @@ pub fn getConfig() -> Vec<SpriteGroup> {
                     animation_length: 5,
                     source_range_index: (0, 85),
                     draw_as_shadow: true,
+                    timing: FrameTiming::iscriptWaits(vec![1; 5]),
                     ..DEFAULT_SPRITE_FORMAT.clone()
                 },
                 SpriteFormat {
@@ -291,6 +671,7 @@ pub fn getConfig() -> Vec<SpriteGroup> {
                     animation_length: 8,
                     source_range_index: (85, 221),
                     draw_as_shadow: true,
+                    timing: FrameTiming::iscriptWaits(vec![1; 8]),
                     ..DEFAULT_SPRITE_FORMAT.clone()
                 },
             ],
@@ -308,6 +689,8 @@ pub fn getConfig() -> Vec<SpriteGroup> {
                     draw_as_glow: true,
                     scalable: true,
                     split_anim: true,
+                    // ShieldOverlayInit
+                    timing: FrameTiming::iscriptWaits(vec![2; 4]),
                     ..DEFAULT_SPRITE_FORMAT.clone()
                 },
             ],
@@ -323,6 +706,8 @@ pub fn getConfig() -> Vec<SpriteGroup> {
                     animation_length: 14,
                     source_range_index: (0, 14),
                     draw_as_glow: true,
+                    // ExplosionMediumInit
+                    timing: FrameTiming::iscriptWaits(vec![3; 14]),
                     ..DEFAULT_SPRITE_FORMAT.clone()
                 },
             ],
@@ -338,6 +723,8 @@ pub fn getConfig() -> Vec<SpriteGroup> {
                     animation_length: 5,
                     source_range_index: (0, 5),
                     draw_as_glow: true,
+                    // DragoonBallInit
+                    timing: FrameTiming::iscriptWaits(vec![1; 5]),
                     ..DEFAULT_SPRITE_FORMAT.clone()
                 },
             ],
@@ -353,6 +740,9 @@ pub fn getConfig() -> Vec<SpriteGroup> {
                     animation_length: 6,
                     source_range_index: (0, 6),
                     extra_offset_x2: (-600, 0),
+                    packing: Packing::tight,
+                    // PsiStormInit
+                    timing: FrameTiming::iscriptWaits(vec![1; 6]),
                     ..DEFAULT_SPRITE_FORMAT.clone()
                 },
             ],
@@ -369,6 +759,8 @@ pub fn getConfig() -> Vec<SpriteGroup> {
                     source_range_index: (0, 34),
                     used_directions: Option::from(vec![8]),
                     frame_sequence: Option::from(vec![1, 2, 1, 2, 1, 2]),
+                    // ArchonBeamInit
+                    timing: FrameTiming::iscriptWaits(vec![1; 6]),
                     ..DEFAULT_SPRITE_FORMAT.clone()
                 },
                 SpriteFormat {
@@ -378,6 +770,8 @@ pub fn getConfig() -> Vec<SpriteGroup> {
                     source_range_index: (34, 68),
                     used_directions: Option::from(vec![8]),
                     frame_sequence: Option::from(vec![1, 2, 1, 2, 1, 2]),
+                    // ArchonBeamInit
+                    timing: FrameTiming::iscriptWaits(vec![1; 6]),
                     ..DEFAULT_SPRITE_FORMAT.clone()
                 },
             ],
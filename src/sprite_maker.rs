@@ -1,60 +1,360 @@
-use crate::anim::Anim;
-use crate::sprite_config::SpriteFormat;
-use image::{RgbaImage, GenericImageView, GenericImage, Pixel};
-use std::f32::consts::PI;
+use crate::anim::{Anim, Frame};
+use crate::sprite_config::{SpriteFormat, Packing};
+use crate::run_config::{SheetLimits, SdFilter};
+use crate::direction::{Facing, mapDirections, sourceFacing};
+use crate::debug_overlay::{DebugCell, renderDebugSheets};
+use crate::sheet_layout::{SheetLayout, AtlasLayout, gridLayout, tightLayout, stripeLayout, atlasLayout};
+use image::{RgbaImage, Rgba, GenericImageView, GenericImage, Pixel, ImageBuffer};
 use image::imageops::{overlay, crop_imm, resize, FilterType};
 use std::error::Error;
+use rayon::prelude::*;
 
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, PartialEq)]
 pub enum Preset {
     normal,
     mask,
     light,
 }
 
+#[derive(Clone)]
+pub struct Stripe {
+    pub width_in_frames: i32,
+    pub height_in_frames: i32,
+}
+
+/// Where one frame is on the sheets.
+#[derive(Clone)]
+pub struct FrameRect {
+    pub img_idx: usize,
+    pub x: u32,
+    pub y: u32,
+    pub width: i32,
+    pub height: i32,
+    /// Offset of the frame center from its anchor, in half HD pixels.
+    pub shift_x2: (i32, i32),
+}
+
 #[derive(Clone)]
 pub struct FactorioSprites {
     pub images: Vec<RgbaImage>,
     pub slice: i32,
     pub lines_per_file: i32,
+    /// Set when the sheets are laid out as Factorio `stripes`, one per image.
+    pub stripes: Option<Vec<Stripe>>,
     pub width: i32,
     pub height: i32,
+    /// Offset of the cell center from the frame anchor, in half HD pixels.
+    pub shift_x2: (i32, i32),
+    /// Copies of `images` with the debug overlay drawn in, when it was requested.
+    pub debug_images: Vec<RgbaImage>,
+    /// Set when every frame is trimmed and placed on its own, to be written as one
+    /// sprite per frame. The cell fields then don't apply.
+    pub frames: Option<Vec<FrameRect>>,
 }
 
-pub fn makeSprites(
+impl FactorioSprites {
+    /// Returns the image index and the top-left pixel of the cell holding frame `idx`.
+    pub fn framePosition(&self, idx: i32) -> (usize, u32, u32) {
+        let (img_idx, cell_idx, line_length) = match &self.stripes {
+            Some(stripes) => {
+                let mut first = 0;
+                let mut position = None;
+                for (img_idx, stripe) in stripes.iter().enumerate() {
+                    let frame_count = stripe.width_in_frames * stripe.height_in_frames;
+                    if idx < first + frame_count {
+                        position = Some((img_idx, idx - first, stripe.width_in_frames));
+                        break;
+                    }
+                    first += frame_count;
+                }
+                position.expect("frame index is outside of the stripes")
+            }
+            None => (
+                (idx / (self.slice * self.lines_per_file)) as usize,
+                idx % (self.slice * self.lines_per_file),
+                self.slice,
+            ),
+        };
+        (
+            img_idx,
+            (cell_idx % line_length * self.width) as u32,
+            (cell_idx / line_length * self.height) as u32,
+        )
+    }
+
+    /// Where frame `idx` is and how it is shifted, for a sprite of its own.
+    pub fn frameRect(&self, idx: i32) -> FrameRect {
+        if let Some(frames) = &self.frames {
+            return frames[idx as usize].clone();
+        }
+        let (img_idx, x, y) = self.framePosition(idx);
+        FrameRect { img_idx, x, y, width: self.width, height: self.height, shift_x2: self.shift_x2 }
+    }
+}
+
+/// Allocates blank sheets for `layout`.
+fn emptySprites(layout: &SheetLayout, shift_x2: (i32, i32)) -> FactorioSprites {
+    FactorioSprites {
+        images: layout.images.iter()
+            .map(|image| RgbaImage::new(image.width as u32, image.height as u32))
+            .collect(),
+        slice: layout.images[0].columns,
+        lines_per_file: layout.images[0].rows,
+        stripes: if layout.stripes {
+            Some(layout.images.iter()
+                .map(|image| Stripe { width_in_frames: image.columns, height_in_frames: image.rows })
+                .collect())
+        } else {
+            None
+        },
+        width: layout.cell_width,
+        height: layout.cell_height,
+        shift_x2,
+        debug_images: Vec::new(),
+        frames: None,
+    }
+}
+
+/// Allocates blank sheets for `layout`, with the frames of `sizes` at its placements.
+fn emptyAtlasSprites(layout: &AtlasLayout, sizes: &[(i32, i32)], shifts_x2: &[(i32, i32)]) -> FactorioSprites {
+    FactorioSprites {
+        images: layout.images.iter()
+            .map(|&(width, height)| RgbaImage::new(width as u32, height as u32))
+            .collect(),
+        slice: 1,
+        lines_per_file: 1,
+        stripes: None,
+        width: sizes.iter().map(|size| size.0).max().unwrap_or(0),
+        height: sizes.iter().map(|size| size.1).max().unwrap_or(0),
+        shift_x2: (0, 0),
+        debug_images: Vec::new(),
+        frames: Some(layout.placements.iter()
+            .zip(sizes.iter().zip(shifts_x2))
+            .map(|(&(img_idx, x, y), (&(width, height), &shift_x2))| FrameRect {
+                img_idx,
+                x: x as u32,
+                y: y as u32,
+                width,
+                height,
+                shift_x2,
+            })
+            .collect()),
+    }
+}
+
+fn checkFileCount(file_count: usize, format: &SpriteFormat, limits: &SheetLimits) -> Result<(), Box<dyn Error + Send + Sync>> {
+    match limits.max_files {
+        Some(max_files) if file_count as i32 > max_files => Err(format!(
+            "{} needs {} sheets, more than the maximum of {}",
+            format.name,
+            file_count,
+            max_files,
+        ).into()),
+        _ => Ok(()),
+    }
+}
+
+fn hasLayers(frame: &Frame, preset: Preset) -> bool {
+    frame.diffuse.is_some() && match preset {
+        Preset::normal => true,
+        Preset::mask => frame.teamcolor.is_some(),
+        Preset::light => frame.emissive.is_some(),
+    }
+}
+
+/// The pixel `preset` draws at (`x`, `y`) of the frame, or `None` if it leaves it out.
+/// The frame must have the layers required by the preset.
+fn presetPixel(frame: &Frame, preset: Preset, x: u32, y: u32) -> Option<Rgba<u8>> {
+    unsafe {
+        match preset {
+            Preset::normal => Some(frame.diffuse.as_ref().unwrap().unsafe_get_pixel(x, y)),
+            Preset::mask => {
+                let masked = frame.teamcolor.as_ref().unwrap()
+                    .unsafe_get_pixel(x, y)
+                    .channels()[0] > 0;
+                if masked {
+                    Some(frame.diffuse.as_ref().unwrap().unsafe_get_pixel(x, y))
+                } else {
+                    None
+                }
+            }
+            Preset::light => {
+                let mut p = frame.emissive.as_ref().unwrap()
+                    .unsafe_get_pixel(x, y)
+                    .to_rgba();
+                let channels = p.channels_mut();
+                channels[3] = channels[0]
+                    .max(channels[1])
+                    .max(channels[2]);
+                Some(p)
+            }
+        }
+    }
+}
+
+/// Bounding box (x0, y0, x1, y1) of the visible pixels of an image.
+fn contentBounds(image: &RgbaImage) -> Option<(i32, i32, i32, i32)> {
+    let mut bounds: Option<(i32, i32, i32, i32)> = None;
+    for (x, y, p) in image.enumerate_pixels() {
+        if p.channels()[3] == 0 { continue; }
+        let (x, y) = (x as i32, y as i32);
+        bounds = Some(match bounds {
+            Some((x0, y0, x1, y1)) => (x0.min(x), y0.min(y), x1.max(x + 1), y1.max(y + 1)),
+            None => (x, y, x + 1, y + 1),
+        });
+    }
+    bounds
+}
+
+/// Draws a frame as one picture, cropped to its visible pixels, with the team colour
+/// areas multiplied by `tint` as a tinted mask layer would. `None` for frames without a
+/// diffuse layer or visible pixels.
+pub fn renderIcon(frame: &Frame, mirrored: bool, tint: Option<(u8, u8, u8)>) -> Option<RgbaImage> {
+    if !hasLayers(frame, Preset::normal) {
+        return None;
+    }
+    let tint = tint.filter(|_| hasLayers(frame, Preset::mask));
+    let mut image = RgbaImage::new(frame.width as u32, frame.height as u32);
+    for x in 0..frame.width {
+        for y in 0..frame.height {
+            let source_x = (if mirrored { frame.width - x - 1 } else { x }) as u32;
+            let mut p = presetPixel(frame, Preset::normal, source_x, y as u32).unwrap();
+            if let Some((r, g, b)) = tint {
+                if presetPixel(frame, Preset::mask, source_x, y as u32).is_some() {
+                    p[0] = (p[0] as u16 * r as u16 / 255) as u8;
+                    p[1] = (p[1] as u16 * g as u16 / 255) as u8;
+                    p[2] = (p[2] as u16 * b as u16 / 255) as u8;
+                }
+            }
+            image.put_pixel(x as u32, y as u32, p);
+        }
+    }
+    let (x0, y0, x1, y1) = contentBounds(&image)?;
+    Some(crop_imm(&image, x0 as u32, y0 as u32, (x1 - x0) as u32, (y1 - y0) as u32).to_image())
+}
+
+/// A source frame as it is drawn into one cell, with the preset applied and the facing's
+/// mirroring and rotation done.
+struct CellFrame {
+    image: RgbaImage,
+    center_x2: (i32, i32),
+    source_idx: usize,
+    radial_offset_x2: (i32, i32),
+}
+
+/// Rotates `image` clockwise by `angle` radians around its center, growing it to fit.
+fn rotateFrame(image: &RgbaImage, center_x2: (i32, i32), angle: f32) -> (RgbaImage, (i32, i32)) {
+    let (sin, cos) = angle.sin_cos();
+    let center = (center_x2.0 as f32 / 2.0, center_x2.1 as f32 / 2.0);
+    let rotate = |x: f32, y: f32| (
+        center.0 + (x - center.0) * cos - (y - center.1) * sin,
+        center.1 + (x - center.0) * sin + (y - center.1) * cos,
+    );
+    let (width, height) = (image.width() as f32, image.height() as f32);
+    let corners = [rotate(0.0, 0.0), rotate(width, 0.0), rotate(0.0, height), rotate(width, height)];
+    let min_x = corners.iter().map(|c| c.0).fold(f32::INFINITY, f32::min).floor();
+    let min_y = corners.iter().map(|c| c.1).fold(f32::INFINITY, f32::min).floor();
+    let max_x = corners.iter().map(|c| c.0).fold(f32::NEG_INFINITY, f32::max).ceil();
+    let max_y = corners.iter().map(|c| c.1).fold(f32::NEG_INFINITY, f32::max).ceil();
+
+    let mut output = RgbaImage::new((max_x - min_x) as u32, (max_y - min_y) as u32);
+    for (x, y, p) in output.enumerate_pixels_mut() {
+        // nearest neighbour keeps the pixel art crisp
+        let (out_x, out_y) = (min_x + x as f32 + 0.5, min_y + y as f32 + 0.5);
+        let source_x = center.0 + (out_x - center.0) * cos + (out_y - center.1) * sin;
+        let source_y = center.1 - (out_x - center.0) * sin + (out_y - center.1) * cos;
+        if source_x >= 0.0 && source_y >= 0.0 && source_x < width && source_y < height {
+            *p = *image.get_pixel(source_x as u32, source_y as u32);
+        }
+    }
+    let rotated_center_x2 = (
+        (2.0 * (center.0 - min_x)).round() as i32,
+        (2.0 * (center.1 - min_y)).round() as i32,
+    );
+    (output, rotated_center_x2)
+}
+
+/// Draws a frame for one facing. The radial offset moves the anchor along the heading the
+/// frame is drawn at, `radial_offset_x2` being its reach east and south.
+fn renderCellFrame(
+    frame: &Frame,
+    source_idx: usize,
+    radial_offset_x2: (f32, f32),
+    preset: Preset,
+    facing: Facing,
+) -> CellFrame {
+    let mut image = RgbaImage::new(frame.width as u32, frame.height as u32);
+    for x in 0..frame.width {
+        for y in 0..frame.height {
+            let source_x = if facing.mirrored { frame.width - x - 1 } else { x };
+            if let Some(p) = presetPixel(frame, preset, source_x as u32, y as u32) {
+                image.put_pixel(x as u32, y as u32, p);
+            }
+        }
+    }
+    let mut center_x2 = frame.center_x2;
+    if facing.mirrored {
+        center_x2.0 = 2 * frame.width - center_x2.0;
+    }
+    if facing.rotation != 0.0 {
+        let (rotated, rotated_center_x2) = rotateFrame(&image, center_x2, facing.rotation);
+        image = rotated;
+        center_x2 = rotated_center_x2;
+    }
+    let theta = (facing.heading as f32).to_radians();
+    let radial_offset_x2 = (
+        (radial_offset_x2.0 * theta.sin()).round() as i32,
+        (radial_offset_x2.1 * theta.cos()).round() as i32,
+    );
+    center_x2.0 += radial_offset_x2.0;
+    center_x2.1 += radial_offset_x2.1;
+    CellFrame { image, center_x2, source_idx, radial_offset_x2 }
+}
+
+/// Every output cell of a format, direction by direction, before it is laid out.
+pub struct CollectedCells {
+    cells: Vec<CellFrame>,
+    frame_width: i32,
+    frame_height: i32,
+}
+
+/// Draws the cells of `format` from `anim`, or `None` when the frames have no layers for
+/// `preset`.
+pub fn collectCells(
     anim: &Anim,
     format: &SpriteFormat,
     base_offset_x2: (i32, i32),
     preset: Preset,
-) -> Result<Option<FactorioSprites>, Box<dyn Error>> {
+) -> Result<Option<CollectedCells>, Box<dyn Error + Send + Sync>> {
     let mut frames = Vec::with_capacity((format.source_range_index.1 - format.source_range_index.0) as usize);
     let source_direction_count = (format.source_range_index.1 - format.source_range_index.0) / format.animation_length;
     for i in format.source_range_index.0..format.source_range_index.1 {
         if let Some(frame) = anim.getFrame(i as usize)? { frames.push(frame) };
     }
-    let used_directions = if let Some(directions) = &format.used_directions {
-        if *(&directions.len()) != *(&format.direction_count) as usize {
+    if !frames.first().is_some_and(|frame| hasLayers(frame, preset)) {
+        return Ok(None);
+    }
+    let facings = if let Some(directions) = &format.used_directions {
+        if directions.len() != format.direction_count as usize {
             panic!("format.direction_count and format.used_directions.len() does not match!")
         }
-        directions.clone()
+        directions.iter()
+            .map(|direction| sourceFacing(*direction, source_direction_count, &format.source_orientation))
+            .collect()
     } else {
-        let step = 2 * (source_direction_count - 1) / &format.direction_count;
-        (0..format.direction_count).map(|x| x * step).collect()
+        mapDirections(
+            format.direction_count,
+            &format.orientation,
+            source_direction_count,
+            &format.source_orientation,
+            format.rotate_directions,
+        )
     };
     let mut frame_width = 0;
     let mut frame_height = 0;
-    for i in 0..(format.source_range_index.1 - format.source_range_index.0) {
-        let frame = &mut frames[i as usize];
-        let mut theta = (i % source_direction_count) as f32;
-        if source_direction_count > 1 {
-            theta *= PI / (source_direction_count - 1) as f32;
-        }
-        let radial_offset_x2 = (
-            (format.radial_offset_x2.0 * theta.sin()).round() as i32,
-            (format.radial_offset_x2.1 * theta.cos()).round() as i32,
-        );
-        frame.center_x2.0 += base_offset_x2.0 + radial_offset_x2.0 + format.extra_offset_x2.0;
-        frame.center_x2.1 += base_offset_x2.1 + radial_offset_x2.1 + format.extra_offset_x2.1;
+    for frame in &mut frames {
+        frame.center_x2.0 += base_offset_x2.0 + format.extra_offset_x2.0;
+        frame.center_x2.1 += base_offset_x2.1 + format.extra_offset_x2.1;
         frame_width = frame_width
             .max(frame.center_x2.0)
             .max(2 * frame.width - frame.center_x2.0);
@@ -62,212 +362,462 @@ pub fn makeSprites(
             .max(frame.center_x2.1)
             .max(2 * frame.height - frame.center_x2.1);
     }
-    let col_count = 1.max(4096 / frame_width);
-    let row_count = 1.max(4096 / frame_height);
-    let frame_per_img = if format.split_anim { format.animation_length } else { col_count * row_count };
-    let image_count = (
-        used_directions.len() as i32
-            * format.animation_length
-            + format.empty_pad
-            + frame_per_img
-            - 1
-    ) / frame_per_img;
-
-    let mut output: Vec<RgbaImage> = vec![
-        RgbaImage::new(
-            (col_count * frame_width) as u32,
-            (row_count * frame_height) as u32,
-        );
-        image_count as usize
-    ];
-
-    for ori in 0..used_directions.len() as i32 {
+
+    // every output cell after the empty padding, direction by direction
+    let mut cells = Vec::with_capacity(facings.len() * format.animation_length as usize);
+    for facing in &facings {
         for anim_idx in 0..format.animation_length {
-            let out_idx = ori * format.animation_length + anim_idx + format.empty_pad;
-            let img_idx = out_idx / frame_per_img;
-            let row_idx = (out_idx % frame_per_img as i32) / col_count;
-            let col_idx = (out_idx % frame_per_img) % col_count as i32;
-            let mut source_ori = used_directions[ori as usize];
-            let mirrored = source_ori >= source_direction_count;
-            if mirrored { source_ori = 2 * (source_direction_count - 1) - source_ori; }
-            let source_idx = anim_idx * source_direction_count + source_ori;
-            let frame = &frames[source_idx as usize];
-            let start_x = if mirrored {
-                col_idx * frame_width + (frame_width - 2 * frame.width + frame.center_x2.0) / 2
-            } else {
-                col_idx * frame_width + (frame_width - frame.center_x2.0) / 2
-            } as u32;
-            let start_y = (row_idx * frame_height + (frame_height - frame.center_x2.1) / 2) as u32;
-
-            let diffuse = match &frame.diffuse {
-                Some(img) => img,
-                None => return Ok(None),
+            let source_idx = (anim_idx * source_direction_count + facing.source) as usize;
+            let cell = renderCellFrame(
+                &frames[source_idx],
+                format.source_range_index.0 as usize + source_idx,
+                format.radial_offset_x2,
+                preset,
+                *facing,
+            );
+            frame_width = frame_width
+                .max(cell.center_x2.0)
+                .max(2 * cell.image.width() as i32 - cell.center_x2.0);
+            frame_height = frame_height
+                .max(cell.center_x2.1)
+                .max(2 * cell.image.height() as i32 - cell.center_x2.1);
+            cells.push(cell);
+        }
+    }
+    Ok(Some(CollectedCells { cells, frame_width, frame_height }))
+}
+
+/// Appends the decay cells to the death cells of each direction, `die_length` and
+/// `decay_length` being their frames per direction. Missing decay cells stay blank.
+pub fn joinCells(
+    die: CollectedCells,
+    decay: Option<CollectedCells>,
+    die_length: i32,
+    decay_length: i32,
+) -> CollectedCells {
+    let direction_count = die.cells.len() / die_length as usize;
+    let (mut frame_width, mut frame_height) = (die.frame_width, die.frame_height);
+    let mut decay_cells = match decay {
+        Some(decay) => {
+            frame_width = frame_width.max(decay.frame_width);
+            frame_height = frame_height.max(decay.frame_height);
+            decay.cells.into_iter().map(Some).collect()
+        }
+        None => Vec::new(),
+    };
+    decay_cells.resize_with(direction_count * decay_length as usize, || None);
+    let mut die_cells = die.cells.into_iter();
+    let mut decay_cells = decay_cells.into_iter();
+    let mut cells = Vec::with_capacity(direction_count * (die_length + decay_length) as usize);
+    for _ in 0..direction_count {
+        cells.extend(die_cells.by_ref().take(die_length as usize));
+        cells.extend(decay_cells.by_ref().take(decay_length as usize).map(|cell| {
+            cell.unwrap_or_else(|| CellFrame {
+                image: RgbaImage::new(1, 1),
+                center_x2: (1, 1),
+                source_idx: 0,
+                radial_offset_x2: (0, 0),
+            })
+        }));
+    }
+    CollectedCells { cells, frame_width, frame_height }
+}
+
+/// Visible pixels of a cell as (x0, y0, width, height), the size rounded up to even so
+/// the SD sheets halve exactly. A blank cell keeps a blank 2x2 corner.
+fn trimmedBounds(image: &RgbaImage) -> (i32, i32, i32, i32) {
+    match contentBounds(image) {
+        Some((x0, y0, x1, y1)) => (x0, y0, x1 - x0 + (x1 - x0) % 2, y1 - y0 + (y1 - y0) % 2),
+        None => (0, 0, 2, 2),
+    }
+}
+
+/// Draws every cell trimmed to `frame_bounds` at its place in `layout`.
+fn drawAtlas(
+    cells: &[CellFrame],
+    frame_bounds: &[(i32, i32, i32, i32)],
+    layout: &AtlasLayout,
+    debug_overlay: bool,
+) -> FactorioSprites {
+    let sizes: Vec<(i32, i32)> = frame_bounds.iter().map(|&(_, _, width, height)| (width, height)).collect();
+    let anchors_x2: Vec<(i32, i32)> = cells.iter()
+        .zip(frame_bounds)
+        .map(|(cell, &(x0, y0, _, _))| (cell.center_x2.0 - 2 * x0, cell.center_x2.1 - 2 * y0))
+        .collect();
+    let shifts_x2: Vec<(i32, i32)> = sizes.iter()
+        .zip(&anchors_x2)
+        .map(|(&(width, height), &(anchor_x, anchor_y))| (width - anchor_x, height - anchor_y))
+        .collect();
+    let mut sprites = emptyAtlasSprites(layout, &sizes, &shifts_x2);
+    let mut debug_cells = Vec::new();
+    for (idx, cell) in cells.iter().enumerate() {
+        let (img_idx, frame_x, frame_y) = layout.placements[idx];
+        let (x0, y0, width, height) = frame_bounds[idx];
+        for y in 0..height.min(cell.image.height() as i32 - y0) {
+            for x in 0..width.min(cell.image.width() as i32 - x0) {
+                let p = *cell.image.get_pixel((x0 + x) as u32, (y0 + y) as u32);
+                sprites.images[img_idx].put_pixel((frame_x + x) as u32, (frame_y + y) as u32, p);
+            }
+        }
+        if debug_overlay {
+            debug_cells.push(DebugCell {
+                img_idx,
+                x: frame_x,
+                y: frame_y,
+                width,
+                height,
+                anchor_x2: anchors_x2[idx],
+                radial_offset_x2: cell.radial_offset_x2,
+                source_idx: cell.source_idx as i32,
+            });
+        }
+    }
+    if debug_overlay {
+        sprites.debug_images = renderDebugSheets(&sprites.images, &debug_cells);
+    }
+    sprites
+}
+
+/// Lays `collected` out on sheets. Tight packing picks whichever of the grid, stripes
+/// and a sprite per frame takes the fewest bytes, and reports what it saves.
+pub fn packCells(
+    collected: &CollectedCells,
+    format: &SpriteFormat,
+    limits: &SheetLimits,
+    debug_overlay: bool,
+) -> Result<FactorioSprites, Box<dyn Error + Send + Sync>> {
+    let (cells, frame_width, frame_height) = (&collected.cells, collected.frame_width, collected.frame_height);
+    let frame_count = cells.len() as i32 + format.empty_pad;
+
+    let split_length = if format.split_anim { Some(format.framesPerDirection()) } else { None };
+    let grid_layout = gridLayout(frame_width, frame_height, frame_count, split_length, limits)
+        .map_err(|err| format!("{}: {}", format.name, err))?;
+    let tight = format.packing == Packing::tight && !format.split_anim;
+    let stripe_layout = if tight && format.target.supportsStripes() {
+        let mut extents_x2: Option<(i32, i32, i32, i32)> = None;
+        for cell in cells {
+            let (x0, y0, x1, y1) = match contentBounds(&cell.image) {
+                Some(bounds) => bounds,
+                None => continue,
             };
-            for x in 0..frame.width as u32 {
-                for y in 0..frame.height as u32 {
-                    unsafe {
-                        let source_x = if mirrored { frame.width as u32 - x - 1 } else { x };
-                        match preset {
-                            Preset::normal => {
-                                let p = diffuse.unsafe_get_pixel(source_x, y);
-                                output[img_idx as usize]
-                                    .unsafe_put_pixel(start_x + x, start_y + y, p);
-                            }
-                            Preset::mask => {
-                                let teamcolor = match &frame.teamcolor {
-                                    Some(img) => img,
-                                    None => return Ok(None),
-                                };
-                                let masked = teamcolor
-                                    .unsafe_get_pixel(source_x, y)
-                                    .channels()[0] > 0;
-                                if !masked { continue; }
-                                let p = diffuse.unsafe_get_pixel(source_x, y);
-                                output[img_idx as usize]
-                                    .unsafe_put_pixel(start_x + x, start_y + y, p);
-                            }
-                            Preset::light => {
-                                let emissive = match &frame.emissive {
-                                    Some(img) => img,
-                                    None => return Ok(None),
-                                };
-                                let mut p = emissive
-                                    .unsafe_get_pixel(source_x, y)
-                                    .to_rgba();
-                                let channels = p.channels_mut();
-                                channels[3] = channels[0]
-                                    .max(channels[1])
-                                    .max(channels[2]);
-                                output[img_idx as usize]
-                                    .unsafe_put_pixel(start_x + x, start_y + y, p);
-                            }
-                        }
-                    }
-                }
+            let cell_extents_x2 = (
+                cell.center_x2.0 - 2 * x0,
+                cell.center_x2.1 - 2 * y0,
+                2 * x1 - cell.center_x2.0,
+                2 * y1 - cell.center_x2.1,
+            );
+            extents_x2 = Some(match extents_x2 {
+                Some((left, top, right, bottom)) => (
+                    left.max(cell_extents_x2.0),
+                    top.max(cell_extents_x2.1),
+                    right.max(cell_extents_x2.2),
+                    bottom.max(cell_extents_x2.3),
+                ),
+                None => cell_extents_x2,
+            });
+        }
+        extents_x2.map(|extents_x2| tightLayout(extents_x2, frame_count, limits)).transpose()?
+    } else {
+        None
+    };
+    let frame_bounds: Vec<(i32, i32, i32, i32)> = if tight && format.target.writesFrameSprites() && format.empty_pad == 0 {
+        cells.iter().map(|cell| trimmedBounds(&cell.image)).collect()
+    } else {
+        Vec::new()
+    };
+    let frame_sizes: Vec<(i32, i32)> = frame_bounds.iter().map(|&(_, _, width, height)| (width, height)).collect();
+    let atlas_layout = atlasLayout(&frame_sizes, limits)
+        .filter(|layout| !matches!(limits.max_files, Some(max_files) if layout.images.len() as i32 > max_files));
+
+    let grid_size = grid_layout.byteSize();
+    let stripe_size = stripe_layout.as_ref().map_or(u64::MAX, |layout| layout.byteSize());
+    let atlas_size = atlas_layout.as_ref().map_or(u64::MAX, |layout| layout.byteSize());
+    let reportSavings = |packing: &str, size: u64| println!(
+        "  {}: {} saves {} bytes versus grid layout ({} -> {})",
+        format.name, packing, grid_size - size, grid_size, size,
+    );
+    if let Some(atlas_layout) = atlas_layout.filter(|_| atlas_size < grid_size.min(stripe_size)) {
+        reportSavings("a sprite per frame", atlas_size);
+        return Ok(drawAtlas(cells, &frame_bounds, &atlas_layout, debug_overlay));
+    }
+    let layout = match stripe_layout.filter(|_| stripe_size < grid_size) {
+        Some(stripe_layout) => {
+            reportSavings("stripes", stripe_size);
+            stripe_layout
+        }
+        None => grid_layout,
+    };
+
+    checkFileCount(layout.images.len(), format, limits)?;
+    let mut sprites = emptySprites(
+        &layout,
+        (layout.cell_width - layout.anchor_x2.0, layout.cell_height - layout.anchor_x2.1),
+    );
+    let output = &mut sprites.images;
+    let mut debug_cells = Vec::new();
+
+    for (cell_idx, cell) in cells.iter().enumerate() {
+        let out_idx = cell_idx as i32 + format.empty_pad;
+        let (img_idx, cell_x, cell_y) = layout.cellPosition(out_idx);
+        let start_x = cell_x + (layout.anchor_x2.0 - cell.center_x2.0).div_euclid(2);
+        let start_y = cell_y + (layout.anchor_x2.1 - cell.center_x2.1).div_euclid(2);
+
+        for (x, y, p) in cell.image.enumerate_pixels() {
+            let out_x = start_x + x as i32;
+            let out_y = start_y + y as i32;
+            if out_x < cell_x || out_x >= cell_x + layout.cell_width
+                || out_y < cell_y || out_y >= cell_y + layout.cell_height {
+                continue;
             }
-            if cfg!(debug_assertions) {
-                unsafe {
-                    let green_pixel = image::Rgba([0, 255, 0, 255]);
-                    let x_center1 = (frame_width - 1) / 2;
-                    let x_center2 = frame_width / 2;
-                    let y_center1 = (frame_height - 1) / 2;
-                    let y_center2 = frame_height / 2;
-                    let corner_x = (frame_width * col_idx) as u32;
-                    let corner_y = (frame_height * row_idx) as u32;
-
-                    for y in 0..frame_height {
-                        let draw;
-                        if y <= y_center1 {
-                            draw = ((y_center1 - y) % 8) >= 4;
-                        } else {
-                            draw = ((y - y_center2) % 8) >= 4;
-                        }
-                        if draw {
-                            output[img_idx as usize]
-                                .unsafe_put_pixel(
-                                    corner_x + x_center1 as u32,
-                                    corner_y + y as u32,
-                                    green_pixel,
-                                );
-                            output[img_idx as usize]
-                                .unsafe_put_pixel(
-                                    corner_x + x_center2 as u32,
-                                    corner_y + y as u32,
-                                    green_pixel,
-                                );
-                        }
-                    }
-                    for x in 0..frame_width {
-                        let draw;
-                        if x <= x_center1 {
-                            draw = ((x_center1 - x) % 8) >= 4;
-                        } else {
-                            draw = ((x - x_center2) % 8) >= 4;
-                        }
-                        if draw {
-                            output[img_idx as usize]
-                                .unsafe_put_pixel(
-                                    corner_x + x as u32,
-                                    corner_y + y_center1 as u32,
-                                    green_pixel,
-                                );
-                            output[img_idx as usize]
-                                .unsafe_put_pixel(
-                                    corner_x + x as u32,
-                                    corner_y + y_center2 as u32,
-                                    green_pixel,
-                                );
-                        }
+            unsafe {
+                output[img_idx].unsafe_put_pixel(out_x as u32, out_y as u32, *p);
+            }
+        }
+        if debug_overlay {
+            debug_cells.push(DebugCell {
+                img_idx,
+                x: cell_x,
+                y: cell_y,
+                width: layout.cell_width,
+                height: layout.cell_height,
+                anchor_x2: layout.anchor_x2,
+                radial_offset_x2: cell.radial_offset_x2,
+                source_idx: cell.source_idx as i32,
+            });
+        }
+    }
+    if debug_overlay {
+        sprites.debug_images = renderDebugSheets(&sprites.images, &debug_cells);
+    }
+    Ok(sprites)
+}
+
+pub fn makeSprites(
+    anim: &Anim,
+    format: &SpriteFormat,
+    base_offset_x2: (i32, i32),
+    preset: Preset,
+    limits: &SheetLimits,
+    debug_overlay: bool,
+) -> Result<Option<FactorioSprites>, Box<dyn Error + Send + Sync>> {
+    match collectCells(anim, format, base_offset_x2, preset)? {
+        Some(collected) => Ok(Some(packCells(&collected, format, limits, debug_overlay)?)),
+        None => Ok(None),
+    }
+}
+
+/// Sprites of a death animation with the decay frames of `format.corpse`, taken from
+/// `decay_anim`, following each direction.
+pub fn makeCorpseSprites(
+    anim: &Anim,
+    decay_anim: &Anim,
+    format: &SpriteFormat,
+    base_offset_x2: (i32, i32),
+    preset: Preset,
+    limits: &SheetLimits,
+    debug_overlay: bool,
+) -> Result<Option<FactorioSprites>, Box<dyn Error + Send + Sync>> {
+    let corpse = format.corpse.as_ref().expect("format has no decay frames");
+    let die = match collectCells(anim, format, base_offset_x2, preset)? {
+        Some(die) => die,
+        None => return Ok(None),
+    };
+    let decay_format = SpriteFormat {
+        source_range_index: corpse.source_range_index,
+        animation_length: corpse.animation_length,
+        corpse: None,
+        ..format.clone()
+    };
+    let decay = collectCells(decay_anim, &decay_format, corpse.base_offset_x2, preset)?;
+    let joined = joinCells(die, decay, format.animation_length, corpse.animation_length);
+    Ok(Some(packCells(&joined, format, limits, debug_overlay)?))
+}
+
+fn srgbToLinear(value: f32) -> f32 {
+    if value <= 0.04045 {
+        value / 12.92
+    } else {
+        ((value + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+fn linearToSrgb(value: f32) -> f32 {
+    if value <= 0.0031308 {
+        value * 12.92
+    } else {
+        1.055 * value.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+/// Resizes a frame in premultiplied linear light, so transparent texels do not bleed
+/// their color into the edges.
+pub fn downscaleFrame<I: GenericImageView<Pixel = Rgba<u8>>>(
+    source: &I,
+    width: u32,
+    height: u32,
+    filter: SdFilter,
+) -> RgbaImage {
+    let to_linear: Vec<f32> = (0..256).map(|v| srgbToLinear(v as f32 / 255.0)).collect();
+    let mut linear: ImageBuffer<Rgba<f32>, Vec<f32>> = ImageBuffer::new(source.width(), source.height());
+    for (x, y, p) in source.pixels() {
+        let alpha = p[3] as f32 / 255.0;
+        linear.put_pixel(x, y, Rgba([
+            to_linear[p[0] as usize] * alpha,
+            to_linear[p[1] as usize] * alpha,
+            to_linear[p[2] as usize] * alpha,
+            alpha,
+        ]));
+    }
+    let resized = match filter {
+        SdFilter::boxAverage => {
+            let mut resized: ImageBuffer<Rgba<f32>, Vec<f32>> = ImageBuffer::new(width, height);
+            let (scale_x, scale_y) = (source.width() / width.max(1), source.height() / height.max(1));
+            for (x, y, p) in resized.enumerate_pixels_mut() {
+                let mut sum = [0.0f32; 4];
+                for source_x in x * scale_x..(x + 1) * scale_x {
+                    for source_y in y * scale_y..(y + 1) * scale_y {
+                        let q = linear.get_pixel(source_x, source_y);
+                        for c in 0..4 { sum[c] += q[c]; }
                     }
                 }
+                let count = (scale_x * scale_y).max(1) as f32;
+                *p = Rgba([sum[0] / count, sum[1] / count, sum[2] / count, sum[3] / count]);
             }
+            resized
         }
+        SdFilter::triangle => resize(&linear, width, height, FilterType::Triangle),
+        SdFilter::lanczos3 => resize(&linear, width, height, FilterType::Lanczos3),
+        SdFilter::catmullRom => resize(&linear, width, height, FilterType::CatmullRom),
+    };
+    let mut output = RgbaImage::new(width, height);
+    for (x, y, p) in resized.enumerate_pixels() {
+        let alpha = p[3].clamp(0.0, 1.0);
+        if alpha <= 0.0 { continue; }
+        let color = |c: f32| (linearToSrgb((c / alpha).clamp(0.0, 1.0)) * 255.0).round() as u8;
+        output.put_pixel(x, y, Rgba([color(p[0]), color(p[1]), color(p[2]), (alpha * 255.0).round() as u8]));
     }
-    Ok(Some(FactorioSprites {
-        images: output,
-        slice: col_count as i32,
-        lines_per_file: row_count as i32,
-        width: frame_width as i32,
-        height: frame_height as i32,
-    }))
+    output
+}
+
+/// SD version of sheets with a sprite per frame, every frame halved and packed anew for
+/// the SD limits.
+fn makeFrameSpritesSd(
+    hd_sprites: &FactorioSprites,
+    frames: &[FrameRect],
+    format: &SpriteFormat,
+    limits: &SheetLimits,
+    filter: SdFilter,
+) -> Result<FactorioSprites, Box<dyn Error + Send + Sync>> {
+    let sizes: Vec<(i32, i32)> = frames.iter().map(|frame| (frame.width / 2, frame.height / 2)).collect();
+    let shifts_x2: Vec<(i32, i32)> = frames.iter().map(|frame| frame.shift_x2).collect();
+    let layout = atlasLayout(&sizes, limits)
+        .ok_or_else(|| format!("{}: a frame doesn't fit on an SD sheet", format.name))?;
+    checkFileCount(layout.images.len(), format, limits)?;
+    let mut sd_sprites = emptyAtlasSprites(&layout, &sizes, &shifts_x2);
+    let sd_frames: Vec<RgbaImage> = frames
+        .par_iter()
+        .map(|frame| {
+            let source = crop_imm(
+                &hd_sprites.images[frame.img_idx],
+                frame.x,
+                frame.y,
+                frame.width as u32,
+                frame.height as u32,
+            );
+            downscaleFrame(&source, frame.width as u32 / 2, frame.height as u32 / 2, filter)
+        })
+        .collect();
+    for (&(img_idx, x, y), sd_frame) in layout.placements.iter().zip(&sd_frames) {
+        overlay(&mut sd_sprites.images[img_idx], sd_frame, x as u32, y as u32);
+    }
+    Ok(sd_sprites)
 }
 
 pub fn makeSpritesSd(
     hd_sprites: &FactorioSprites,
     format: &SpriteFormat,
-) -> FactorioSprites {
+    limits: &SheetLimits,
+    filter: SdFilter,
+) -> Result<FactorioSprites, Box<dyn Error + Send + Sync>> {
+    if let Some(frames) = &hd_sprites.frames {
+        return makeFrameSpritesSd(hd_sprites, frames, format, limits, filter);
+    }
     let sd_width = hd_sprites.width / 2;
     let sd_height = hd_sprites.height / 2;
-    let col_count = 1.max(2048 / sd_width);
-    let row_count = 1.max(2048 / sd_height);
-    let frame_per_img = if format.split_anim { format.animation_length } else { col_count * row_count };
-    let image_count = (
-        format.direction_count
-            * format.animation_length
-            + format.empty_pad
-            + frame_per_img
-            - 1
-    ) / frame_per_img;
-    let mut output: Vec<RgbaImage> = vec![
-        RgbaImage::new(
-            (sd_width * col_count) as u32,
-            (sd_height * row_count) as u32,
-        );
-        image_count as usize
-    ];
-    for idx in 0..(format.direction_count * format.animation_length + format.empty_pad) {
-        let source_img_idx = idx / (hd_sprites.lines_per_file * hd_sprites.slice);
-        let source_row_idx = (idx / hd_sprites.slice) % hd_sprites.lines_per_file;
-        let source_col_idx = idx % hd_sprites.slice;
-
-        let out_img_idx = idx / (col_count * row_count);
-        let out_row_idx = (idx / col_count) % row_count;
-        let out_col_idx = idx % col_count;
-
-        let source = crop_imm(
-            &hd_sprites.images[source_img_idx as usize],
-            (source_col_idx * hd_sprites.width) as u32,
-            (source_row_idx * hd_sprites.height) as u32,
-            hd_sprites.width as u32,
-            hd_sprites.height as u32,
-        );
-        overlay(
-            &mut output[out_img_idx as usize],
-            &resize(
-                &source,
-                sd_width as u32,
-                sd_height as u32,
-                FilterType::Lanczos3,
-            ),
-            (out_col_idx * sd_width) as u32,
-            (out_row_idx * sd_height) as u32,
-        );
+    let frame_count = format.direction_count * format.framesPerDirection() + format.empty_pad;
+    let layout = match &hd_sprites.stripes {
+        Some(stripes) => stripeLayout(stripes, sd_width, sd_height, limits),
+        None => {
+            let split_length = if format.split_anim { Some(format.framesPerDirection()) } else { None };
+            gridLayout(sd_width, sd_height, frame_count, split_length, limits)
+        }
+    }.map_err(|err| format!("{}: {}", format.name, err))?;
+    checkFileCount(layout.images.len(), format, limits)?;
+    let mut sd_sprites = emptySprites(&layout, hd_sprites.shift_x2);
+    let sd_frames: Vec<RgbaImage> = (0..frame_count)
+        .into_par_iter()
+        .map(|idx| {
+            let (source_img_idx, source_x, source_y) = hd_sprites.framePosition(idx);
+            let source = crop_imm(
+                &hd_sprites.images[source_img_idx],
+                source_x,
+                source_y,
+                hd_sprites.width as u32,
+                hd_sprites.height as u32,
+            );
+            downscaleFrame(&source, sd_width as u32, sd_height as u32, filter)
+        })
+        .collect();
+    for (idx, sd_frame) in sd_frames.iter().enumerate() {
+        let (out_img_idx, out_x, out_y) = sd_sprites.framePosition(idx as i32);
+        overlay(&mut sd_sprites.images[out_img_idx], sd_frame, out_x, out_y);
     }
-    FactorioSprites {
-        images: output,
-        slice: col_count as i32,
-        lines_per_file: row_count as i32,
-        width: sd_width,
-        height: sd_height,
+    Ok(sd_sprites)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sprite_config::OutputTarget;
+
+    const LIMITS: SheetLimits = SheetLimits { max_size: 256, power_of_two: false, max_files: None };
+
+    /// A 32x32 cell centered on its middle with a `width` by `height` block at `(x, y)`.
+    fn cell(x: u32, y: u32, width: u32, height: u32) -> CellFrame {
+        let mut image = RgbaImage::new(32, 32);
+        for py in y..y + height {
+            for px in x..x + width {
+                image.put_pixel(px, py, Rgba([255, 0, 0, 255]));
+            }
+        }
+        CellFrame { image, center_x2: (32, 32), source_idx: 0, radial_offset_x2: (0, 0) }
+    }
+
+    #[test]
+    fn tightSprite4WayPacksEveryFrameOnItsOwn() {
+        let collected = CollectedCells {
+            cells: vec![cell(0, 0, 4, 6), cell(26, 26, 6, 6), cell(0, 28, 3, 4), cell(28, 0, 4, 2)],
+            frame_width: 32,
+            frame_height: 32,
+        };
+        let format = SpriteFormat {
+            name: String::from("base"),
+            direction_count: 4,
+            animation_length: 1,
+            packing: Packing::tight,
+            target: OutputTarget::sprite4Way,
+            ..SpriteFormat::default()
+        };
+        let sprites = packCells(&collected, &format, &LIMITS, false).unwrap();
+        let frames = sprites.frames.as_ref().expect("a sprite per frame");
+        let sizes: Vec<(i32, i32)> = frames.iter().map(|frame| (frame.width, frame.height)).collect();
+        assert_eq!(sizes, vec![(4, 6), (6, 6), (4, 4), (4, 2)]);
+        // the anchor stays at the cell center, measured from the trimmed frame
+        assert_eq!(frames[0].shift_x2, (4 - 32, 6 - 32));
+        assert_eq!(frames[1].shift_x2, (6 + 20, 6 + 20));
+        for frame in frames {
+            let image = &sprites.images[frame.img_idx];
+            assert_eq!(*image.get_pixel(frame.x, frame.y), Rgba([255, 0, 0, 255]));
+        }
     }
 }
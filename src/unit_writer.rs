@@ -0,0 +1,371 @@
+use std::error::Error;
+use std::fs::File;
+use std::io::Write;
+use crate::casc::CascStorage;
+use crate::dat::{readUnits, readWeapons, readFlingies, Weapon, DamageType, NO_WEAPON};
+use crate::factorio_anim_writer::{corpseName, corpseFrameSequence, corpseTime};
+use crate::lua;
+use crate::lua::LuaSyntax;
+use crate::run_config::{RunConfig, FactorioVersion};
+use crate::sprite_config::{SpriteGroup, SpriteFormat, UnitConfig, GAME_FRAMES_PER_SECOND, TICKS_PER_SECOND};
+
+const PIXELS_PER_TILE: f64 = 32.0;
+
+fn findFormat<'a>(metadata: &'a [SpriteGroup], category: &str, name: &str) -> Option<&'a SpriteFormat> {
+    metadata.iter()
+        .filter(|group| group.category == category)
+        .flat_map(|group| group.sprites.iter())
+        .find(|format| format.name == name)
+}
+
+/// Call of the `anim.lua` function for format `format_name` of the unit, the
+/// composition of the same name when there is one.
+fn animationCall(
+    unit: &UnitConfig,
+    format_name: &str,
+    entries: &[(String, lua::Exp)],
+) -> Result<lua::Exp, Box<dyn Error + Send + Sync>> {
+    let name = format!("{}_{}", unit.category, format_name).replace("-", "_");
+    let layered = entries.iter()
+        .find(|(key, _)| key == "layered")
+        .and_then(|(_, compositions)| match compositions {
+            lua::Exp::Table { field_list } => field_list.iter().find(|(key, _)| *key == name),
+            _ => None,
+        });
+    let (path, function) = match layered {
+        Some((_, function)) => (format!("anim.layered.{}", name), function),
+        None => match entries.iter().find(|(key, _)| *key == name) {
+            Some((_, function)) => (format!("anim.{}", name), function),
+            None => return Err(format!("{}: no animation {} in anim.lua", unit.name, name).into()),
+        },
+    };
+    let par_list = match function {
+        lua::Exp::Function { par_list, .. } => par_list,
+        _ => return Err(format!("{}: anim.lua entry {} is not a function", unit.name, name).into()),
+    };
+    let tint = lua::Exp::table(vec![
+        ("r", lua::Exp::number(unit.tint.0 as f64 / 255.0)?),
+        ("g", lua::Exp::number(unit.tint.1 as f64 / 255.0)?),
+        ("b", lua::Exp::number(unit.tint.2 as f64 / 255.0)?),
+        ("a", lua::Exp::integer(1)),
+    ]);
+    lua::Exp::call(
+        &path,
+        par_list.iter()
+            .map(|par| match &par[..] {
+                "scale" => lua::Exp::integer(1),
+                "tint" => tint.clone(),
+                _ => lua::Exp::Nil,
+            })
+            .collect(),
+    )
+}
+
+fn unitFormat<'a>(
+    metadata: &'a [SpriteGroup],
+    unit: &UnitConfig,
+    format_name: &str,
+) -> Result<&'a SpriteFormat, Box<dyn Error + Send + Sync>> {
+    findFormat(metadata, &unit.category, format_name)
+        .ok_or_else(|| format!("{}: no format {} in {}", unit.name, format_name, unit.category).into())
+}
+
+/// A flingy.dat speed, in 1/256 pixels per game frame, in tiles per tick.
+fn tilesPerTick(top_speed: u32) -> f64 {
+    top_speed as f64 / 256.0 * GAME_FRAMES_PER_SECOND / TICKS_PER_SECOND / PIXELS_PER_TILE
+}
+
+/// The damage of one attack of `weapon`, all of its hits together.
+fn damageEffect(weapon: &Weapon) -> lua::Exp {
+    // explosive damage is the only StarCraft type with a close match in Factorio
+    let damage_type = match weapon.damage_type {
+        DamageType::explosive => "explosion",
+        _ => "physical",
+    };
+    lua::Exp::table(vec![
+        ("type", lua::Exp::string("damage")),
+        (
+            "damage",
+            lua::Exp::table(vec![
+                ("amount", lua::Exp::integer(weapon.damage_amount as i64 * weapon.damage_factor as i64)),
+                ("type", lua::Exp::string(damage_type)),
+            ])
+        ),
+    ])
+}
+
+/// An action dealing the damage of `weapon` to whatever it is applied to.
+fn directDamage(weapon: &Weapon) -> lua::Exp {
+    lua::Exp::table(vec![
+        ("type", lua::Exp::string("direct")),
+        (
+            "action_delivery",
+            lua::Exp::table(vec![
+                ("type", lua::Exp::string("instant")),
+                ("target_effects", damageEffect(weapon)),
+            ])
+        ),
+    ])
+}
+
+/// The projectile a ranged weapon fires, dealing the damage where it hits.
+fn projectilePrototype(name: &str, weapon: &Weapon) -> Result<lua::Exp, Box<dyn Error + Send + Sync>> {
+    Ok(lua::Exp::table(vec![
+        ("type", lua::Exp::string("projectile")),
+        ("name", lua::Exp::string(name)),
+        ("flags", lua::Exp::Array { member_list: vec![lua::Exp::string("not-on-map")] }),
+        ("acceleration", lua::Exp::integer(0)),
+        ("action", directDamage(weapon)),
+    ]))
+}
+
+/// How the unit attacks with `weapon`: weapons that strike the target straight away hit
+/// it directly, ranged ones fire `projectile`, given by name and speed in tiles per tick.
+fn attackParameters(
+    unit: &UnitConfig,
+    weapon: &Weapon,
+    projectile: Option<&(String, f64)>,
+    entries: &[(String, lua::Exp)],
+) -> Result<lua::Exp, Box<dyn Error + Send + Sync>> {
+    // the ammo categories of the base game's biters and spitters
+    let (ammo_category, action) = match projectile {
+        Some((name, speed)) => (
+            "biological",
+            lua::Exp::table(vec![
+                ("type", lua::Exp::string("direct")),
+                (
+                    "action_delivery",
+                    lua::Exp::table(vec![
+                        ("type", lua::Exp::string("projectile")),
+                        ("projectile", lua::Exp::string(name)),
+                        ("starting_speed", lua::Exp::number(*speed)?),
+                    ])
+                ),
+            ]),
+        ),
+        None => ("melee", directDamage(weapon)),
+    };
+    let mut fields = vec![
+        ("type", lua::Exp::string("projectile")),
+        ("range", lua::Exp::number(weapon.max_range as f64 / PIXELS_PER_TILE)?),
+    ];
+    if weapon.min_range > 0 {
+        fields.push(("min_range", lua::Exp::number(weapon.min_range as f64 / PIXELS_PER_TILE)?));
+    }
+    fields.extend(vec![
+        ("cooldown", lua::Exp::number(weapon.cooldown as f64 * TICKS_PER_SECOND / GAME_FRAMES_PER_SECOND)?),
+        ("ammo_category", lua::Exp::string(ammo_category)),
+        (
+            "ammo_type",
+            lua::Exp::table(vec![
+                ("category", lua::Exp::string(ammo_category)),
+                ("target_type", lua::Exp::string("entity")),
+                ("action", action),
+            ])
+        ),
+        ("animation", animationCall(unit, &unit.attack, entries)?),
+    ]);
+    Ok(lua::Exp::table(fields))
+}
+
+/// Writes `units.lua` with a unit prototype per configured unit, plus its projectile,
+/// death explosion and corpse, from units.dat, weapons.dat and flingy.dat and the `anim.lua` entries.
+pub fn writeUnits(
+    storage: &mut CascStorage,
+    units: &[UnitConfig],
+    metadata: &[SpriteGroup],
+    entries: &[(String, lua::Exp)],
+    config: &RunConfig,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let units_dat = readUnits(storage.openFile("arr/units.dat")?)?;
+    let weapons_dat = readWeapons(storage.openFile("arr/weapons.dat")?)?;
+    let flingy_dat = readFlingies(storage.openFile("arr/flingy.dat")?)?;
+
+    let mut stats = vec![
+        lua::Stat::Local {
+            name_list: vec![String::from("anim")],
+            exp_list: vec![lua::Exp::call("require", vec![lua::Exp::string("anim")])?],
+        },
+    ];
+    let mut prototypes = Vec::new();
+    for unit in units {
+        let data = units_dat.get(unit.unit_id)
+            .ok_or_else(|| format!("{}: no unit {} in units.dat", unit.name, unit.unit_id))?;
+        if data.ground_weapon == NO_WEAPON {
+            println!("  {}: unit {} has no ground weapon, skipping", unit.name, unit.unit_id);
+            continue;
+        }
+        let weapon = weapons_dat.get(data.ground_weapon as usize)
+            .ok_or_else(|| format!("{}: no weapon {} in weapons.dat", unit.name, data.ground_weapon))?;
+        let flingy = flingy_dat.get(data.flingy as usize)
+            .ok_or_else(|| format!("{}: no flingy {} in flingy.dat", unit.name, data.flingy))?;
+        // ranged weapons fire a projectile of their own, at the speed of its flingy
+        let projectile_speed = flingy_dat.get(weapon.flingy as usize)
+            .map(|flingy| tilesPerTick(flingy.top_speed))
+            .filter(|speed| *speed > 0.0);
+        let projectile = match projectile_speed {
+            Some(speed) if weapon.behavior.firesProjectile() => {
+                let name = format!("{}-projectile", unit.name);
+                prototypes.push(projectilePrototype(&name, weapon)?);
+                Some((name, speed))
+            }
+            _ => None,
+        };
+
+        let movement_speed = tilesPerTick(flingy.top_speed);
+        // shields are not modelled, they count as extra health
+        let max_health = data.hit_points as f64 / 256.0
+            + if data.shield_enable { data.shield_amount as f64 } else { 0.0 };
+        let dimensions = data.dimensions;
+        let bounding_box = lua::Exp::Array {
+            member_list: vec![
+                lua::Exp::Array {
+                    member_list: vec![
+                        lua::Exp::number(-(dimensions.left as f64) / PIXELS_PER_TILE)?,
+                        lua::Exp::number(-(dimensions.up as f64) / PIXELS_PER_TILE)?,
+                    ]
+                },
+                lua::Exp::Array {
+                    member_list: vec![
+                        lua::Exp::number((dimensions.right + 1) as f64 / PIXELS_PER_TILE)?,
+                        lua::Exp::number((dimensions.down + 1) as f64 / PIXELS_PER_TILE)?,
+                    ]
+                },
+            ]
+        };
+
+        let mut fields = vec![
+            ("type", lua::Exp::string("unit")),
+            ("name", lua::Exp::string(&unit.name)),
+            (
+                "flags",
+                lua::Exp::Array {
+                    member_list: vec![
+                        lua::Exp::string("placeable-player"),
+                        lua::Exp::string("placeable-enemy"),
+                        lua::Exp::string("placeable-off-grid"),
+                    ]
+                }
+            ),
+            ("subgroup", lua::Exp::string("enemies")),
+            ("max_health", lua::Exp::number(max_health)?),
+        ];
+        let icon = entries.iter()
+            .find(|(key, _)| key == "icons")
+            .and_then(|(_, icons)| match icons {
+                lua::Exp::Table { field_list } => field_list.iter().find(|(key, _)| *key == unit.category),
+                _ => None,
+            });
+        if let Some((_, lua::Exp::Table { field_list })) = icon {
+            fields.extend(field_list.iter().map(|(key, exp)| (&key[..], exp.clone())));
+        }
+        if data.armor > 0 {
+            fields.push((
+                "resistances",
+                lua::Exp::Array {
+                    member_list: vec![lua::Exp::table(vec![
+                        ("type", lua::Exp::string("physical")),
+                        ("decrease", lua::Exp::integer(data.armor as i64)),
+                    ])]
+                }
+            ));
+        }
+        fields.extend(vec![
+            ("collision_box", bounding_box.clone()),
+            ("selection_box", bounding_box.clone()),
+            ("vision_distance", lua::Exp::integer(data.sight_range as i64)),
+            ("movement_speed", lua::Exp::number(movement_speed)?),
+            (
+                "distance_per_frame",
+                lua::Exp::number(movement_speed / unitFormat(metadata, unit, &unit.run)?.timing.animationSpeed())?
+            ),
+            match config.factorio_version {
+                FactorioVersion::v1_1 => ("pollution_to_join_attack", lua::Exp::integer(0)),
+                FactorioVersion::v2_0 => ("absorptions_to_join_attack", lua::Exp::table(vec![("pollution", lua::Exp::integer(0))])),
+            },
+            ("distraction_cooldown", lua::Exp::integer(300)),
+            (
+                "ai_settings",
+                lua::Exp::table(vec![
+                    ("destroy_when_commands_fail", lua::Exp::Bool(true)),
+                    ("allow_try_return_to_spawner", lua::Exp::Bool(true)),
+                ])
+            ),
+            ("run_animation", animationCall(unit, &unit.run, entries)?),
+            ("attack_parameters", attackParameters(unit, weapon, projectile.as_ref(), entries)?),
+        ]);
+
+        let joined_die = unit.die.as_ref()
+            .filter(|die| findFormat(metadata, &unit.category, die).is_some_and(|format| format.corpse.is_some()));
+        if let Some(die) = joined_die {
+            if unit.corpse.is_some() {
+                return Err(format!("{}: {} already has decay frames, drop the corpse format", unit.name, die).into());
+            }
+            // the corpse prototype plays the death animation itself
+            fields.push(("corpse", lua::Exp::string(&corpseName(&unit.category, die))));
+            prototypes.push(animationCall(unit, &format!("{}_corpse", die), entries)?);
+        } else if let Some(die) = &unit.die {
+            let explosion_name = format!("{}-die", unit.name);
+            fields.push(("dying_explosion", lua::Exp::string(&explosion_name)));
+            prototypes.push(lua::Exp::table(vec![
+                ("type", lua::Exp::string("explosion")),
+                ("name", lua::Exp::string(&explosion_name)),
+                ("flags", lua::Exp::Array { member_list: vec![lua::Exp::string("not-on-map")] }),
+                ("animations", animationCall(unit, die, entries)?),
+            ]));
+        }
+        if let Some(corpse) = &unit.corpse {
+            let die = unit.die.as_ref()
+                .ok_or_else(|| format!("{}: a corpse needs a die animation to time it", unit.name))?;
+            let die_frames = unitFormat(metadata, unit, die)?.animation_length;
+            let corpse_frames = unitFormat(metadata, unit, corpse)?.animation_length;
+            let corpse_name = format!("{}-corpse", unit.name);
+            let animation = format!("{}_corpse_animation", unit.name).replace("-", "_");
+            // the first, empty frame is shown while the death explosion plays
+            let frame_sequence = lua::Exp::Array {
+                member_list: corpseFrameSequence(die_frames, corpse_frames).into_iter()
+                    .map(|frame| lua::Exp::integer(frame as i64))
+                    .collect(),
+            };
+            stats.push(lua::Stat::Local {
+                name_list: vec![animation.clone()],
+                exp_list: vec![animationCall(unit, corpse, entries)?],
+            });
+            stats.push(lua::Stat::Assignment {
+                var_list: vec![lua::Exp::path(&format!("{}.frame_sequence", animation))?],
+                exp_list: vec![frame_sequence.clone()],
+            });
+            if config.factorio_version == FactorioVersion::v1_1 {
+                stats.push(lua::Stat::Assignment {
+                    var_list: vec![lua::Exp::path(&format!("{}.hr_version.frame_sequence", animation))?],
+                    exp_list: vec![frame_sequence],
+                });
+            }
+            fields.push(("corpse", lua::Exp::string(&corpse_name)));
+            prototypes.push(lua::Exp::table(vec![
+                ("type", lua::Exp::string("corpse")),
+                ("name", lua::Exp::string(&corpse_name)),
+                (
+                    "flags",
+                    lua::Exp::Array {
+                        member_list: vec![
+                            lua::Exp::string("placeable-neutral"),
+                            lua::Exp::string("placeable-off-grid"),
+                            lua::Exp::string("not-on-map"),
+                        ]
+                    }
+                ),
+                ("subgroup", lua::Exp::string("corpses")),
+                ("selectable_in_game", lua::Exp::Bool(false)),
+                ("selection_box", bounding_box),
+                ("time_before_removed", lua::Exp::integer(corpseTime(die_frames, corpse_frames))),
+                ("animation", lua::Exp::var(&animation)?),
+            ]));
+        }
+        prototypes.push(lua::Exp::table(fields));
+    }
+    let block = lua::wrap(stats, lua::Exp::Array { member_list: prototypes }, config.prototype_wrapper);
+    let mut file = File::create(format!("{}/units.lua", config.output_dir))?;
+    file.write_all(block.print(&config.lua_style).as_ref())?;
+    Ok(())
+}